@@ -1,8 +1,9 @@
 use lazy_static::lazy_static;
+use rasterize::{capture::Recorder, image, present::Present};
 use sdl2::{
     event::Event,
     keyboard::{Keycode, Scancode},
-    pixels::Color,
+    pixels::{Color, PixelFormatEnum},
     render::Canvas,
     video::Window,
     Sdl,
@@ -68,6 +69,25 @@ impl Engine {
         }
         self.canvas.draw_point((x, y)).unwrap();
     }
+    /// What has been drawn since the last present, at window resolution.
+    pub fn read_rgb(&self) -> (u32, u32, Vec<u32>) {
+        let (width, height) = self.canvas.output_size().unwrap();
+        let bytes = self
+            .canvas
+            .read_pixels(None, PixelFormatEnum::ABGR8888)
+            .unwrap();
+        let rgb = bytes
+            .chunks(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], 0]))
+            .collect();
+        (width, height, rgb)
+    }
+    pub fn screenshot(&self) {
+        let (width, height, rgb) = self.read_rgb();
+        let path = image::numbered_path("screenshot", "ppm");
+        image::write_ppm(&path, width, height, &rgb).unwrap();
+        println!("screenshot: {:?}", path);
+    }
 }
 
 fn get_color(color_index: i32) -> Color {
@@ -306,6 +326,8 @@ fn main() {
         },
     ];
 
+    let mut screenshot = false;
+    let mut recorder = Recorder::new();
     'mainloop: loop {
         let mut event_pump = engine.sdl_context.event_pump().unwrap();
         for event in event_pump.poll_iter() {
@@ -315,6 +337,14 @@ fn main() {
                     keycode: Option::Some(Keycode::Escape),
                     ..
                 } => break 'mainloop,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => println!("{}", recorder.toggle("capture", "y4m").unwrap()),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } => screenshot = true,
                 _ => {}
             }
         }
@@ -374,6 +404,14 @@ fn main() {
         engine.draw_pixel(W as i32 - 1, H as i32 - 1, 0);
 
         draw3d(&player, &mut sectors, &walls, &mut engine);
+        if screenshot {
+            engine.screenshot();
+            screenshot = false;
+        }
+        if recorder.is_recording() {
+            let (width, height, rgb) = engine.read_rgb();
+            recorder.present(width, height, &rgb).unwrap();
+        }
         engine.canvas.present();
 
         std::thread::sleep(std::time::Duration::from_millis(16));
@@ -0,0 +1,97 @@
+// Renders the walkthrough level without a window and writes the frame to a file. For .gif and
+// .y4m the camera turns around once over a number of frames.
+//
+// usage: headless <out.ppm|out.pcx|out.gif|out.y4m> [x z yaw] [--truncate] [--frames n]
+use std::path::Path;
+
+use rasterize::{
+    capture::Recorder,
+    image,
+    math::prelude::*,
+    palette::{self, Framebuffer, LightModel},
+    present::Headless,
+    rasterize::{FillRule, RasterStats},
+    scene::{Camera, Scene},
+};
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: headless <out.ppm|out.pcx|out.gif|out.y4m> [x z yaw] [--truncate] [--frames n]"
+    );
+    std::process::exit(1);
+}
+
+fn main() {
+    const W: u32 = 320;
+    const H: u32 = 240;
+
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    let fill_rule = match args.iter().position(|arg| arg == "--truncate") {
+        Some(i) => {
+            args.remove(i);
+            FillRule::Truncate
+        }
+        None => FillRule::TopLeft,
+    };
+    let mut num_frames = 60;
+    if let Some(i) = args.iter().position(|arg| arg == "--frames") {
+        num_frames = args
+            .get(i + 1)
+            .and_then(|frames| frames.parse().ok())
+            .unwrap_or_else(|| usage());
+        args.drain(i..i + 2);
+    }
+    let Some(out) = args.first() else {
+        usage();
+    };
+    let mut camera = Camera::default();
+    if let [x, z, yaw] = &args[1..] {
+        camera.pos = Vec3::new(x.parse().unwrap(), 0.0, z.parse().unwrap());
+        camera.yaw = yaw.parse().unwrap();
+    }
+
+    let (palette, mapping_table) = palette::read_colormap();
+    let scene = Scene::example(&palette);
+    let mut fb = Framebuffer::new(W, H, &palette);
+    let mut stats = RasterStats::default();
+    let out = Path::new(out);
+    if out
+        .extension()
+        .is_some_and(|ext| ext == "gif" || ext == "y4m")
+    {
+        let mut recorder = Recorder::new();
+        recorder.start(out, Some(num_frames)).unwrap();
+        for frame in 0..num_frames {
+            let yaw = camera.yaw + frame as f32 / num_frames as f32 * std::f32::consts::TAU;
+            let camera = Camera { yaw, ..camera };
+            scene.render(
+                &mut fb,
+                &camera,
+                fill_rule,
+                &mapping_table,
+                LightModel::default(),
+                &mut stats,
+            );
+            recorder.capture(&fb).unwrap();
+        }
+        println!("{}", stats);
+        return;
+    }
+
+    scene.render(
+        &mut fb,
+        &camera,
+        fill_rule,
+        &mapping_table,
+        LightModel::default(),
+        &mut stats,
+    );
+    println!("{}", stats);
+    if out.extension().is_some_and(|ext| ext == "pcx") {
+        palette::write_pcx(out, W, H, &fb.framebuffer, fb.palette()).unwrap();
+    } else {
+        let mut headless = Headless::new();
+        fb.present(&mut headless).unwrap();
+        image::write_ppm(out, W, H, &headless.rgb).unwrap();
+    }
+}
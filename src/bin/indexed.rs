@@ -1,10 +1,15 @@
 use std::fs;
 
 use rasterize::{
-    palette::{self, quantize},
+    capture::Recorder,
+    image,
+    palette::{self, ColormapSpec},
+    present::Sdl,
+    quantize::{ImageDither, PaletteBuilder, Quantizer},
     test_texture,
+    texture::DitherKernel,
 };
-use sdl2::{event::Event, keyboard::Keycode, pixels::PixelFormatEnum, render::TextureAccess};
+use sdl2::{event::Event, keyboard::Keycode};
 
 fn main() {
     const ZOOM: u32 = 4;
@@ -26,7 +31,7 @@ fn main() {
         .map_err(|e| e.to_string())
         .unwrap();
 
-    let mut canvas = window
+    let canvas = window
         .into_canvas()
         .software()
         // .present_vsync()
@@ -35,21 +40,42 @@ fn main() {
         .unwrap();
 
     let texture_creator = canvas.texture_creator();
-    let mut texture = texture_creator
-        .create_texture(
-            PixelFormatEnum::ABGR8888,
-            TextureAccess::Streaming,
-            256,
-            256,
-        )
-        .unwrap();
+    let mut sdl = Sdl::new(canvas, &texture_creator, 256, 256);
 
     let (palette, mapping_table) = palette::read_colormap();
     // let mut pixels = [0u32; 16 * 16];
 
-    let mut fb = palette::Framebuffer::new(256, 256, &palette);
+    let rgb = test_texture::create();
+    // COLORMAP.PCX, or a palette built for the test texture with a generated colormap
+    let built_palette = PaletteBuilder::new().add_image(&rgb).build();
+    let palettes = [
+        (palette, mapping_table.to_vec()),
+        (
+            built_palette,
+            ColormapSpec {
+                levels: 64,
+                ..Default::default()
+            }
+            .generate(&built_palette),
+        ),
+    ];
+    let mut palette_index = 0;
 
-    let test_texture = quantize(&palette, &test_texture::create());
+    let mut fb = palette::Framebuffer::new(256, 256, &palettes[palette_index].0);
+    let dither_modes = [
+        ImageDither::None,
+        ImageDither::FloydSteinberg,
+        ImageDither::Ordered {
+            kernel: DitherKernel::Bayer4x4,
+            spread: 32,
+        },
+    ];
+    let mut dither_mode = 0;
+    let mut recorder = Recorder::new();
+    let quantize = |palette: &[u32; palette::NUM_COLORS], dither| {
+        Quantizer::new(palette).quantize_image(test_texture::TW, test_texture::TH, &rgb, dither)
+    };
+    let mut test_texture = quantize(&palettes[palette_index].0, dither_modes[dither_mode]);
     // fb.framebuffer.copy_from_slice(&test_texture[..]);
     let mut i = 0;
     'mainloop: loop {
@@ -68,6 +94,35 @@ fn main() {
                         i += y;
                     }
                 }
+                Event::KeyDown {
+                    keycode: Some(Keycode::D),
+                    ..
+                } => {
+                    dither_mode = (dither_mode + 1) % dither_modes.len();
+                    println!("dither: {:?}", dither_modes[dither_mode]);
+                    test_texture = quantize(&palettes[palette_index].0, dither_modes[dither_mode]);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    ..
+                } => {
+                    palette_index = (palette_index + 1) % palettes.len();
+                    println!("palette: {}", ["COLORMAP.PCX", "built"][palette_index]);
+                    fb = palette::Framebuffer::new(256, 256, &palettes[palette_index].0);
+                    test_texture = quantize(&palettes[palette_index].0, dither_modes[dither_mode]);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => println!("{}", recorder.toggle("capture", "gif").unwrap()),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } => {
+                    let path = image::numbered_path("screenshot", "pcx");
+                    fb.save_pcx(&path).unwrap();
+                    println!("screenshot: {:?}", path);
+                }
                 _ => (),
             }
         }
@@ -80,11 +135,11 @@ fn main() {
         //         //     palette[mapping_table[i as usize % mapping_table.len()][y * 16 + x] as usize];
         //     }
         // }
+        let mapping_table = &palettes[palette_index].1;
         for (d, s) in fb.framebuffer.iter_mut().zip(test_texture.iter()) {
             *d = mapping_table[i as usize % mapping_table.len()][*s as usize];
         }
-        fb.upload(&mut texture);
-        canvas.copy(&texture, None, None).unwrap();
-        canvas.present();
+        fb.present(&mut sdl).unwrap();
+        recorder.capture(&fb).unwrap();
     }
 }
@@ -0,0 +1,110 @@
+// Walkthrough of the texture_indexed level in a plain terminal, e.g. over ssh. Needs a terminal
+// with 24 bit colors. Unix only, run with `cargo run --features terminal --bin terminal`.
+//
+// keys: w/s or up/down move, a/d strafe, left/right or z/x turn, f fill rule, c colormap,
+// +/- pixels per character, r start/stop recording, q or escape quit
+use std::{io, time::Duration};
+
+use rasterize::{
+    capture::Recorder,
+    palette::{self, ColormapSpec, Framebuffer, LightModel},
+    present::Ansi,
+    rasterize::{FillRule, RasterStats},
+    scene::{Camera, Scene},
+    terminal::{Key, RawTerminal},
+};
+
+fn main() {
+    const W: u32 = 320;
+    const H: u32 = 240;
+
+    let (palette, mapping_table) = palette::read_colormap();
+    let scene = Scene::example(&palette);
+    let mut fb = Framebuffer::new(W, H, &palette);
+    // COLORMAP.PCX and a generated one fading into fog, like in texture_indexed
+    let fog = ColormapSpec {
+        fade_color: 0x707070,
+        fullbright: vec![224..=255],
+        ..Default::default()
+    };
+    let colormaps = [
+        (mapping_table.to_vec(), LightModel::default()),
+        (fog.generate(&palette), fog.light_model()),
+    ];
+    let mut colormap_index = 0;
+    let mut fill_rule = FillRule::TopLeft;
+    let mut recorder = Recorder::new();
+    let mut camera = Camera::default();
+
+    let mut terminal = RawTerminal::new().unwrap();
+    // smallest downscale that fits the terminal
+    let scale = match terminal.size() {
+        // some terminals report 0 columns or rows, at a scale of W the picture fits in one cell
+        Some((columns, rows)) => (1..=W)
+            .find(|scale| {
+                W.div_ceil(*scale) <= columns.max(1) && H.div_ceil(2 * scale) <= rows.max(1)
+            })
+            .unwrap(),
+        None => 2,
+    };
+    let mut ansi = Ansi::new(io::stdout(), scale);
+    let mut error = None;
+
+    'mainloop: loop {
+        for key in terminal.keys() {
+            match key {
+                Key::Char('q') | Key::Escape => break 'mainloop,
+                Key::Char('w') | Key::Up => camera.pos += camera.forward() * 2.0,
+                Key::Char('s') | Key::Down => camera.pos -= camera.forward() * 2.0,
+                Key::Char('d') => camera.pos += camera.right() * 2.0,
+                Key::Char('a') => camera.pos -= camera.right() * 2.0,
+                Key::Char('z') | Key::Left => camera.yaw += 3.0f32.to_radians(),
+                Key::Char('x') | Key::Right => camera.yaw -= 3.0f32.to_radians(),
+                Key::Char('f') => {
+                    fill_rule = match fill_rule {
+                        FillRule::Truncate => FillRule::TopLeft,
+                        FillRule::TopLeft => FillRule::Truncate,
+                    };
+                }
+                Key::Char('c') => colormap_index = (colormap_index + 1) % colormaps.len(),
+                Key::Char('+') => {
+                    ansi.scale = ansi.scale.saturating_sub(1).max(1);
+                    print!("\x1b[2J");
+                }
+                Key::Char('-') => {
+                    ansi.scale += 1;
+                    print!("\x1b[2J");
+                }
+                Key::Char('r') => {
+                    // no message, it would end up in the picture
+                    recorder.toggle("capture", "gif").unwrap();
+                }
+                _ => (),
+            }
+        }
+
+        let (colormap, light_model) = &colormaps[colormap_index];
+        scene.render(
+            &mut fb,
+            &camera,
+            fill_rule,
+            colormap,
+            *light_model,
+            &mut RasterStats::default(),
+        );
+        // e.g. a closed pipe or a dropped ssh connection, leave through the normal shutdown
+        if let Err(err) = fb.present(&mut ansi) {
+            error = Some(err);
+            break;
+        }
+        recorder.capture(&fb).unwrap();
+        std::thread::sleep(Duration::from_millis(30));
+    }
+    recorder.stop().unwrap();
+    // restores the terminal before the message
+    drop(terminal);
+    if let Some(err) = error {
+        eprintln!("terminal output failed: {}", err);
+        std::process::exit(1);
+    }
+}
@@ -2,11 +2,16 @@
 use std::time::Instant;
 
 use rasterize::{
-    clip_polygon, clip_polygon_inplace,
+    capture::Recorder,
+    clip_polygon, clip_polygon_inplace, halfspace, image,
     math::{self, prelude::*},
+    present::{Present, Sdl},
+    rasterize::{FillRule, RasterStats, Scissor},
+    span::{SpanShader, TruecolorShader},
     test_texture, texpoly, texpoly_vec,
+    texture::{Dither, DitherKernel, Filter, Mipmaps, Texture},
 };
-use sdl2::{event::Event, keyboard::Keycode, pixels::PixelFormatEnum};
+use sdl2::{event::Event, keyboard::Keycode};
 
 fn main() {
     const ZOOM: u32 = 4;
@@ -28,7 +33,7 @@ fn main() {
         .map_err(|e| e.to_string())
         .unwrap();
 
-    let mut canvas = window
+    let canvas = window
         .into_canvas()
         .software()
         // .present_vsync()
@@ -37,14 +42,7 @@ fn main() {
         .unwrap();
 
     let texture_creator = canvas.texture_creator();
-    let mut texture = texture_creator
-        .create_texture(
-            PixelFormatEnum::ABGR8888,
-            sdl2::render::TextureAccess::Streaming,
-            W,
-            H,
-        )
-        .unwrap();
+    let mut sdl = Sdl::new(canvas, &texture_creator, W, H);
 
     let tw = test_texture::TW as f32;
     let th = test_texture::TH as f32;
@@ -76,38 +74,25 @@ fn main() {
     // let mut new_triangle = VecDeque::new();
     let mut click_index = 0;
     let bitmap = test_texture::create();
-    let mut test_texture = texture_creator
-        .create_texture(
-            PixelFormatEnum::ABGR8888,
-            sdl2::render::TextureAccess::Streaming,
-            test_texture::TW as u32,
-            test_texture::TH as u32,
-        )
-        .unwrap();
-
-    test_texture
-        .update(
-            None,
-            unsafe { std::mem::transmute::<&[u32], &[u8]>(&bitmap) },
-            4 * test_texture::TW,
-        )
-        .unwrap();
-
+    let mipmaps = Mipmaps::new(Texture::new(
+        test_texture::TW as u32,
+        test_texture::TH as u32,
+        bitmap.to_vec(),
+    ));
     let l = Vec3::new(0.0, 0.0, -13.0);
 
     let (perspective_project, perspective_unproject) = math::perspective(W as f32, H as f32, 90.0);
     let mut r = 0.0;
     let mut debug_overdraw = false;
     let mut draw_texels = true;
-    let mut bayer_dither = false;
-    const BAYER4X4_F: [[f32; 4]; 4] = [
-        // 4x4 ordered-dithering matrix
-        [0.0 / 16.0, 8.0 / 16.0, 1.0 / 16.0, 9.0 / 16.0],
-        [12.0 / 16.0, 4.0 / 16.0, 13.0 / 16.0, 5.0 / 16.0],
-        [3.0 / 16.0, 11.0 / 16.0, 2.0 / 16.0, 10.0 / 16.0],
-        [15.0 / 16.0, 7.0 / 16.0, 14.0 / 16.0, 6.0 / 16.0],
-    ];
+    let mut recorder = Recorder::new();
+    let mut dither = None;
+    let mut filter = None;
+    let mut zbuffer = vec![f32::MAX; (W * H) as usize];
+    let mut fill_rule = FillRule::Truncate;
+    let mut use_halfspace = false;
 
+    let scissor = Scissor::from_size(W, H);
     let frustum = rasterize::make_frustum(
         &[
             Vec2::ZERO,
@@ -140,7 +125,43 @@ fn main() {
                     Keycode::Z => r -= std::f32::consts::PI / 180.0,
                     Keycode::D => debug_overdraw = !debug_overdraw,
                     Keycode::T => draw_texels = !draw_texels,
-                    Keycode::B => bayer_dither = !bayer_dither,
+                    Keycode::B => {
+                        dither = match dither {
+                            None => Some(Dither::new(DitherKernel::Bayer2x2)),
+                            Some(Dither {
+                                kernel: DitherKernel::Bayer2x2,
+                                ..
+                            }) => Some(Dither::new(DitherKernel::Bayer4x4)),
+                            Some(_) => None,
+                        };
+                        println!("dither: {:?}", dither);
+                    }
+                    Keycode::L => {
+                        filter = match filter {
+                            None => Some(Filter::Nearest),
+                            Some(Filter::Nearest) => Some(Filter::Bilinear),
+                            Some(Filter::Bilinear) => Some(Filter::Trilinear),
+                            Some(Filter::Trilinear) => None,
+                        };
+                        println!("span shader filter: {:?}", filter);
+                    }
+                    Keycode::F => {
+                        fill_rule = match fill_rule {
+                            FillRule::Truncate => FillRule::TopLeft,
+                            FillRule::TopLeft => FillRule::Truncate,
+                        };
+                        println!("fill rule: {:?}", fill_rule);
+                    }
+                    Keycode::H => {
+                        use_halfspace = !use_halfspace;
+                        println!("halfspace rasterizer: {}", use_halfspace);
+                    }
+                    Keycode::F9 => println!("{}", recorder.toggle("capture", "y4m").unwrap()),
+                    Keycode::F12 => {
+                        let path = image::numbered_path("screenshot", "ppm");
+                        image::write_ppm(&path, W, H, &pixels).unwrap();
+                        println!("screenshot: {:?}", path);
+                    }
                     _ => (),
                 },
                 _ => {}
@@ -153,10 +174,12 @@ fn main() {
         let mut color = 0x3b0103a5u32;
         let duplicate = 0xffaa55u32;
         pixels.fill(blank);
+        zbuffer.fill(f32::MAX);
 
         let start = Instant::now();
-        rasterize::rasterize::G_COUNT.store(0, std::sync::atomic::Ordering::SeqCst);
+        let mut stats = RasterStats::default();
         let mut num_texel = 0;
+        let mut overdraw = 0;
         for (p0, p1, p2, p3) in quads.iter().cloned() {
             color = (color << 1) | (color >> (32 - 1));
             let poly_indexed = [p0, p1, p2, p3];
@@ -171,6 +194,7 @@ fn main() {
             }
 
             if poly.len() < 3 {
+                stats.clipped += 1;
                 continue;
             }
             let poly = poly
@@ -187,27 +211,26 @@ fn main() {
                 0x808080, 0x80, 0x8000, 0x800000,
             ];
             // let clipped_polygon = vec![transform(p0), transform(p1), transform(p2), transform(p3)]
-            texpoly::draw_polygon(&poly[..], |x, y, _z, u, v, aux| {
-                if x < 0 || x >= W as i32 || y < 0 || y >= H as i32 {
-                    panic!("out of bounds");
-                }
+            let fragment = |x: i32, y: i32, _z: f32, u: f32, v: f32, aux: u32| {
                 let x = x as usize;
                 let y = y as usize;
                 let pixel_index = y * W as usize + x;
 
                 if true {
                     let pixel = unsafe { pixels.get_unchecked_mut(pixel_index) };
+                    if *pixel != blank {
+                        overdraw += 1;
+                    }
                     if *pixel != blank && debug_overdraw {
                         *pixel = duplicate;
                     } else {
                         if draw_texels {
-                            let (ui, vi) = if bayer_dither {
-                                (
-                                    (u + BAYER4X4_F[y % 4][x % 4]) as usize,
-                                    (v + BAYER4X4_F[y % 4][x % 4]) as usize,
-                                )
-                            } else {
-                                (u as usize, v as usize)
+                            let (ui, vi) = match dither {
+                                Some(dither) => {
+                                    let (du, dv) = dither.offset(x as i32, y as i32);
+                                    ((u - 0.5 + du) as usize, (v - 0.5 + dv) as usize)
+                                }
+                                None => (u as usize, v as usize),
                             };
                             let color = unsafe {
                                 bitmap.get_unchecked(
@@ -229,8 +252,40 @@ fn main() {
                     };
                 }
                 num_texel += 1;
-            });
+            };
+            if let Some(filter) = filter {
+                let shader = TruecolorShader {
+                    filter,
+                    dither,
+                    ..TruecolorShader::new(&mipmaps)
+                };
+                let fragments = stats.fragments;
+                let mut shader_stats = RasterStats::default();
+                texpoly::draw_polygon_spans(
+                    &poly[..],
+                    fill_rule,
+                    &scissor,
+                    i32::MIN..i32::MAX,
+                    &mut stats,
+                    |span| {
+                        let row = span.y as usize * W as usize..(span.y as usize + 1) * W as usize;
+                        shader.draw_span(
+                            span,
+                            &mut pixels[row.clone()],
+                            &mut zbuffer[row],
+                            &mut shader_stats,
+                        );
+                    },
+                );
+                num_texel += stats.fragments - fragments;
+                stats += shader_stats;
+            } else if use_halfspace {
+                halfspace::draw_polygon(&poly[..], &scissor, &mut stats, fragment);
+            } else {
+                texpoly::draw_polygon(&poly[..], fill_rule, &scissor, &mut stats, fragment);
+            }
         }
+        stats.overdraw += overdraw;
 
         let dt = start.elapsed();
         println!(
@@ -240,14 +295,8 @@ fn main() {
             num_texel as f32 * 1e-6 / dt.as_secs_f32(),
             (dt.as_secs_f32() / num_texel as f32) * 2e9
         );
-        texture
-            .update(
-                None,
-                unsafe { std::mem::transmute::<&[u32], &[u8]>(&pixels) },
-                4 * W as usize,
-            )
-            .unwrap();
-        canvas.copy(&texture, None, None).unwrap();
-        canvas.present();
+        println!("{}", stats);
+        sdl.present(W, H, &pixels).unwrap();
+        recorder.present(W, H, &pixels).unwrap();
     }
 }
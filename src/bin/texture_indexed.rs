@@ -1,14 +1,23 @@
 #![feature(step_trait)]
-use std::time::Instant;
+use std::{cmp::Ordering, time::Instant};
 
 use glam::IVec3;
 use rasterize::{
-    clip_polygon, clip_polygon_inplace, level,
+    capture::Recorder,
+    clip_polygon, clip_polygon_inplace, halfspace, image, level,
     math::{self, prelude::*},
-    palette::{self, Framebuffer},
-    test_texture, texpoly, texpoly_vec,
+    palette::{self, ColormapSpec, Framebuffer, LightModel},
+    present::Sdl,
+    rasterize::{FillRule, RasterStats, Scissor},
+    render::{self, Polygon},
+    span::{MipSelect, Perspective, SpanShader, TexturedShader},
+    test_texture,
+    texpoly::{self, Vertex},
+    texpoly_vec,
+    texture::{Dither, DitherKernel, Mipmaps, Wrap, MIP_DEBUG_COLORS},
+    tranmap::Tranmap,
 };
-use sdl2::{event::Event, keyboard::Keycode, pixels::PixelFormatEnum};
+use sdl2::{event::Event, keyboard::Keycode};
 
 fn main() {
     const ZOOM: u32 = 4;
@@ -34,7 +43,7 @@ fn main() {
         .map_err(|e| e.to_string())
         .unwrap();
 
-    let mut canvas = window
+    let canvas = window
         .into_canvas()
         // .software()
         .present_vsync()
@@ -43,14 +52,7 @@ fn main() {
         .unwrap();
 
     let texture_creator = canvas.texture_creator();
-    let mut texture = texture_creator
-        .create_texture(
-            PixelFormatEnum::ABGR8888,
-            sdl2::render::TextureAccess::Streaming,
-            W,
-            H,
-        )
-        .unwrap();
+    let mut sdl = Sdl::new(canvas, &texture_creator, W, H);
 
     let tw = test_texture::TW as f32;
     let th = test_texture::TH as f32;
@@ -66,9 +68,8 @@ fn main() {
     //     Vec3::new(10.0, 10.0, 5.0),
     //     Vec3::new(10.0, -10.0, 5.0),
     // ];
-    let bitmap_wall = palette::read_pcx("assets/wall01.pcx");
-    let bitmap_floor = palette::read_pcx("assets/floor01.pcx");
-    let bitmaps = [&bitmap_wall, &bitmap_floor];
+    let bitmaps = level::MATERIALS.map(|material| Mipmaps::from_indexed(material.load(), &palette));
+    let mip_debug_colors = palette::quantize(&palette, &MIP_DEBUG_COLORS);
 
     // #[rustfmt::skip]
     // let quads = vec![
@@ -84,27 +85,29 @@ fn main() {
     //     ((1, 0.0, 0.0), (5, tw, 0.0), (6, tw, th), (2, 0.0, th), &bitmap_floor),
     // ];
 
-    let floor = [
-        b"................",
-        b"................",
-        b"................",
-        b"................",
-        b"................",
-        b".....1111111....",
-        b".....1..........",
-        b".....1..........",
-        b".....1111.......",
-        b"........1.......",
-        b"........111.....",
-        b"........1.......",
-        b"........1.......",
-        b"........1.......",
-        b".11111111.......",
-        b"................",
-    ];
     let mut level = level::Blockmap::new();
-    level.add(IVec3::ZERO, &floor);
+    level.add(IVec3::ZERO, &level::EXAMPLE_FLOOR);
     let (points, quads) = level.get_polygons();
+    let vertex_lights = level::vertex_lights(&points, 0.25, &level::example_lights());
+    // COLORMAP.PCX, then generated ones fading to black and into fog. The last 32 colors of the
+    // palette are fullbright.
+    let generate = |fade_color| {
+        let spec = ColormapSpec {
+            fade_color,
+            fullbright: vec![224..=255],
+            ..Default::default()
+        };
+        (spec.generate(&palette), spec.light_model())
+    };
+    let colormaps = [
+        (mapping_table.to_vec(), LightModel::default()),
+        generate(0x000000),
+        generate(0x707070),
+    ];
+    let mut colormap_index = 0;
+    let mut gouraud = true;
+    let mut mip_select = MipSelect::Base;
+    let mut mip_debug = false;
     // let mut new_triangle = VecDeque::new();
     let mut click_index = 0;
     let bitmap = palette::quantize(&palette, &test_texture::create());
@@ -115,15 +118,34 @@ fn main() {
     let mut r = 0.0;
     let mut debug_overdraw = false;
     let mut draw_texels = true;
-    let mut bayer_dither = false;
-    const BAYER4X4_F: [[f32; 4]; 4] = [
-        // 4x4 ordered-dithering matrix
-        [0.0 / 16.0, 8.0 / 16.0, 1.0 / 16.0, 9.0 / 16.0],
-        [12.0 / 16.0, 4.0 / 16.0, 13.0 / 16.0, 5.0 / 16.0],
-        [3.0 / 16.0, 11.0 / 16.0, 2.0 / 16.0, 10.0 / 16.0],
-        [15.0 / 16.0, 7.0 / 16.0, 14.0 / 16.0, 6.0 / 16.0],
+    // off, 2x2, 4x4, then both animated
+    let dither_modes = [
+        None,
+        Some((DitherKernel::Bayer2x2, false)),
+        Some((DitherKernel::Bayer4x4, false)),
+        Some((DitherKernel::Bayer2x2, true)),
+        Some((DitherKernel::Bayer4x4, true)),
     ];
+    let mut dither_mode = 0;
+    let mut frame = 0u32;
+    let mut fill_rule = FillRule::Truncate;
+    let mut use_halfspace = false;
+    // 0: per-pixel fragment callback, otherwise span shaders on that many bands
+    let mut num_bands = 0;
+    let mut perspective = Perspective::Exact;
+    // grates are opaque or blended through one of these
+    let tranmaps = [0.25, 0.5, 0.75].map(|opacity| {
+        let path = std::env::temp_dir().join(format!("tranmap{}.bin", (opacity * 100.0) as u32));
+        let (tranmap, written) = Tranmap::cached(&palette, opacity, &path);
+        if let Err(err) = written {
+            eprintln!("failed to write tranmap cache {:?}: {}", path, err);
+        }
+        tranmap
+    });
+    let mut grate_tranmap = None;
+    let mut recorder = Recorder::new();
 
+    let scissor = Scissor::from_size(W, H);
     let frustum = rasterize::make_frustum(
         &[
             Vec2::ZERO,
@@ -161,7 +183,78 @@ fn main() {
                     // Keycode::S => l.z -= 1.0,
                     // Keycode::D => debug_overdraw = !debug_overdraw,
                     Keycode::T => draw_texels = !draw_texels,
-                    Keycode::B => bayer_dither = !bayer_dither,
+                    Keycode::B => {
+                        dither_mode = (dither_mode + 1) % dither_modes.len();
+                        println!("dither (kernel, animated): {:?}", dither_modes[dither_mode]);
+                    }
+                    Keycode::F => {
+                        fill_rule = match fill_rule {
+                            FillRule::Truncate => FillRule::TopLeft,
+                            FillRule::TopLeft => FillRule::Truncate,
+                        };
+                        println!("fill rule: {:?}", fill_rule);
+                    }
+                    Keycode::H => {
+                        use_halfspace = !use_halfspace;
+                        println!("halfspace rasterizer: {}", use_halfspace);
+                    }
+                    Keycode::I => {
+                        mip_select = match mip_select {
+                            MipSelect::Base => MipSelect::PerSpan,
+                            MipSelect::PerSpan => MipSelect::PerPixel,
+                            MipSelect::PerPixel => MipSelect::Base,
+                        };
+                        println!("mipmapping (span shaders only): {:?}", mip_select);
+                    }
+                    Keycode::V => {
+                        mip_debug = !mip_debug;
+                        println!("mip level debug view: {}", mip_debug);
+                    }
+                    Keycode::G => {
+                        gouraud = !gouraud;
+                        println!("gouraud lighting: {}", gouraud);
+                    }
+                    Keycode::P => {
+                        perspective = match perspective {
+                            Perspective::Exact => Perspective::Subdivided(8),
+                            Perspective::Subdivided(8) => Perspective::Subdivided(16),
+                            Perspective::Subdivided(_) => Perspective::Affine,
+                            Perspective::Affine => Perspective::Exact,
+                        };
+                        println!("perspective (span shaders only): {:?}", perspective);
+                    }
+                    Keycode::M => {
+                        num_bands = match num_bands {
+                            0 => 1,
+                            1 => render::default_num_bands(),
+                            _ => 0,
+                        };
+                        println!("span shaders: {} bands", num_bands);
+                    }
+                    Keycode::C => {
+                        colormap_index = (colormap_index + 1) % colormaps.len();
+                        println!(
+                            "colormap: {}",
+                            ["file", "fade to black", "fog"][colormap_index]
+                        );
+                    }
+                    Keycode::O => {
+                        grate_tranmap = match grate_tranmap {
+                            None => Some(0),
+                            Some(i) if i + 1 < tranmaps.len() => Some(i + 1),
+                            Some(_) => None,
+                        };
+                        println!(
+                            "grate opacity (span shaders only): {:?}",
+                            grate_tranmap.map(|i| tranmaps[i].opacity())
+                        );
+                    }
+                    Keycode::F9 => println!("{}", recorder.toggle("capture", "gif").unwrap()),
+                    Keycode::F12 => {
+                        let path = image::numbered_path("screenshot", "pcx");
+                        fb.save_pcx(&path).unwrap();
+                        println!("screenshot: {:?}", path);
+                    }
                     _ => (),
                 },
                 _ => {}
@@ -204,15 +297,51 @@ fn main() {
             fb.framebuffer[(16 + y * W) as usize] = y as u8;
         }
 
+        frame = frame.wrapping_add(1);
+        let dither = dither_modes[dither_mode].map(|(kernel, animated)| Dither {
+            kernel,
+            frame: if animated { frame } else { 0 },
+        });
+        let (colormap, light_model) = &colormaps[colormap_index];
+        let shade = |x: i32, y: i32, z: f32, u: f32, v: f32, light: f32, bi: usize| {
+            let texture = bitmaps[bi].base();
+            let texel = match dither {
+                Some(dither) => texture.sample_dithered(u, v, dither.offset(x, y), Wrap::Repeat),
+                None => texture.sample(u, v, Wrap::Repeat),
+            };
+            if texture.is_transparent(texel) {
+                return None;
+            }
+            Some(colormap[light_model.row(light, z)][texel as usize])
+        };
+
         let start = Instant::now();
-        rasterize::rasterize::G_COUNT.store(0, std::sync::atomic::Ordering::SeqCst);
+        let mut stats = RasterStats::default();
         let mut num_texel = 0;
+        let mut depth_rejected = 0;
+        let mut overdraw = 0;
+        let mut polygons = Vec::new();
         for (p0, p1, p2, p3, bi) in quads.iter().cloned() {
             color = (color << 1) | (color >> (32 - 1));
             let poly_indexed = [p0, p1, p2, p3];
             let mut poly = poly_indexed
                 .iter()
-                .map(|(i, u, v)| (camera_rot * (points[*i as usize] - l), Vec2::new(*u, *v)))
+                .map(|(i, u, v)| {
+                    let light = if gouraud {
+                        vertex_lights[*i as usize]
+                    } else {
+                        1.0
+                    };
+                    let texture = bitmaps[bi].base();
+                    (
+                        camera_rot * (points[*i as usize] - l),
+                        Vec3::new(
+                            *u * texture.width() as f32,
+                            *v * texture.height() as f32,
+                            light,
+                        ),
+                    )
+                })
                 .collect::<Vec<_>>();
 
             for p in frustum.iter() {
@@ -221,23 +350,37 @@ fn main() {
             }
 
             if poly.len() < 3 {
+                stats.clipped += 1;
                 continue;
             }
             let poly = poly
                 .iter()
                 .map(|(p, t)| {
                     let v = perspective_project(*p);
-                    (v.x, v.y, p.z, t.x, t.y)
+                    Vertex {
+                        x: v.x,
+                        y: v.y,
+                        z: p.z,
+                        attributes: t.to_array(),
+                    }
                 })
                 .collect::<Vec<_>>();
 
+            if num_bands > 0 {
+                polygons.push(Polygon {
+                    points: poly,
+                    material: bi,
+                });
+                continue;
+            }
+
             // let transform = |p| p;
             let colors = [
                 0xff, 0xff00, 0xff0000, 0xffff, 0xff00ff, 0xffff00, 0xff8080, 0x80ff80, 0x8080ff,
                 0x808080, 0x80, 0x8000, 0x800000,
             ];
             // let clipped_polygon = vec![transform(p0), transform(p1), transform(p2), transform(p3)]
-            texpoly::draw_polygon(&poly[..], |x, y, z, u, v, aux| {
+            let fragment = |x: i32, y: i32, z: f32, [u, v, light]: [f32; 3], aux: u32| {
                 debug_assert!(x >= 0 && x < W as i32 && y >= 0 && y < H as i32);
                 let x = x as usize;
                 let y = y as usize;
@@ -249,14 +392,7 @@ fn main() {
                     //     *pixel = duplicate;
                     // } else {
                     //     if draw_texels {
-                    //         let (ui, vi) = if bayer_dither {
-                    //             (
-                    //                 (u + BAYER4X4_F[y % 4][x % 4]) as usize,
-                    //                 (v + BAYER4X4_F[y % 4][x % 4]) as usize,
-                    //             )
-                    //         } else {
-                    //             (u as usize, v as usize)
-                    //         };
+                    //         let (ui, vi) = (u as usize, v as usize);
                     //         let color = unsafe {
                     //             bitmap.get_unchecked(
                     //                 (vi % test_texture::TH) * test_texture::TW
@@ -269,17 +405,20 @@ fn main() {
                     //     }
                     // }
                 } else {
+                    // transparent texels of masked textures write neither color nor depth
+                    let color = match shade(x as i32, y as i32, z, u, v, light, bi) {
+                        Some(color) => color,
+                        None => return,
+                    };
                     if z > fb.zbuffer[pixel_index] {
+                        depth_rejected += 1;
                         return;
                     }
+                    if fb.zbuffer[pixel_index] != f32::MAX {
+                        overdraw += 1;
+                    }
                     fb.zbuffer[pixel_index] = z;
-                    let zi = (31 + (z as usize / 2).saturating_sub(16)).min(47);
-                    let u = u as usize % test_texture::TW;
-                    let v = v as usize % test_texture::TH;
-                    let texel_index = u + v * test_texture::TW;
-
-                    fb.framebuffer[pixel_index] =
-                        mapping_table[zi][bitmaps[bi][texel_index as usize] as usize];
+                    fb.framebuffer[pixel_index] = color;
                     // debug_assert!(zi < palette::NUM_GAMMA_RAMP);
                     // debug_assert!(pixel_index < fb.framebuffer.len());
                     // debug_assert!(texel_index < bitmap.len());
@@ -291,7 +430,69 @@ fn main() {
                     // };
                 }
                 num_texel += 1;
+            };
+            if use_halfspace {
+                halfspace::draw_polygon_varyings(&poly[..], &scissor, &mut stats, fragment);
+            } else {
+                texpoly::draw_polygon_varyings(
+                    &poly[..],
+                    fill_rule,
+                    &scissor,
+                    &mut stats,
+                    fragment,
+                );
+            }
+        }
+        stats.depth_rejected += depth_rejected;
+        stats.overdraw += overdraw;
+        if num_bands > 0 {
+            let mut shaders = bitmaps.each_ref().map(|bitmap| TexturedShader {
+                perspective,
+                light: *light_model,
+                mip_select,
+                mip_debug_colors: mip_debug.then_some(&mip_debug_colors[..]),
+                dither,
+                ..TexturedShader::new(bitmap, colormap)
             });
+            shaders[level::MATERIAL_GRATE].tranmap = grate_tranmap.map(|i| &tranmaps[i]);
+            if grate_tranmap.is_some() {
+                // translucent polygons go last, back to front
+                let translucent = |polygon: &Polygon<_>| polygon.material == level::MATERIAL_GRATE;
+                let depth = |polygon: &Polygon<Vertex<3>>| {
+                    polygon.points.iter().map(|p| p.z).sum::<f32>() / polygon.points.len() as f32
+                };
+                polygons.sort_by(|a, b| {
+                    translucent(a).cmp(&translucent(b)).then_with(|| {
+                        if translucent(a) {
+                            depth(b).total_cmp(&depth(a))
+                        } else {
+                            Ordering::Equal
+                        }
+                    })
+                });
+            }
+            let shaders = shaders
+                .each_ref()
+                .map(|shader| shader as &(dyn SpanShader<3> + Sync));
+            render::draw_polygons_banded(
+                &mut fb, &polygons, fill_rule, &scissor, num_bands, &mut stats, &shaders,
+            );
+            num_texel = stats.fragments - stats.depth_rejected;
+
+            if perspective != Perspective::Exact {
+                let mut texel_error = 0.0f32;
+                for polygon in polygons.iter() {
+                    texpoly::draw_polygon_spans(
+                        &polygon.points,
+                        fill_rule,
+                        &scissor,
+                        0..H as i32,
+                        &mut RasterStats::default(),
+                        |span| texel_error = texel_error.max(span.texel_error(perspective)),
+                    );
+                }
+                println!("max texel error: {}", texel_error);
+            }
         }
 
         let dt = start.elapsed();
@@ -302,6 +503,7 @@ fn main() {
             num_texel as f32 * 1e-6 / dt.as_secs_f32(),
             (dt.as_secs_f32() / num_texel as f32) * 2e9
         );
+        println!("{}", stats);
         // texture
         //     .update(
         //         None,
@@ -309,8 +511,7 @@ fn main() {
         //         4 * W as usize,
         //     )
         //     .unwrap();
-        fb.upload(&mut texture);
-        canvas.copy(&texture, None, None).unwrap();
-        canvas.present();
+        fb.present(&mut sdl).unwrap();
+        recorder.capture(&fb).unwrap();
     }
 }
@@ -3,12 +3,16 @@ use std::time::Instant;
 
 use glam::IVec3;
 use rasterize::{
-    clip_polygon, clip_polygon_inplace, level,
+    capture::Recorder,
+    clip_polygon, clip_polygon_inplace, image, level,
     math::{self, prelude::*},
     palette::{self, Framebuffer},
+    present::Sdl,
+    rasterize::{FillRule, RasterStats, Scissor},
     test_texture, texpoly, texpoly_vec,
+    texture::Wrap,
 };
-use sdl2::{event::Event, keyboard::Keycode, pixels::PixelFormatEnum};
+use sdl2::{event::Event, keyboard::Keycode};
 
 fn main() {
     const ZOOM: u32 = 4;
@@ -34,7 +38,7 @@ fn main() {
         .map_err(|e| e.to_string())
         .unwrap();
 
-    let mut canvas = window
+    let canvas = window
         .into_canvas()
         // .software()
         .present_vsync()
@@ -43,14 +47,7 @@ fn main() {
         .unwrap();
 
     let texture_creator = canvas.texture_creator();
-    let mut texture = texture_creator
-        .create_texture(
-            PixelFormatEnum::ABGR8888,
-            sdl2::render::TextureAccess::Streaming,
-            W,
-            H,
-        )
-        .unwrap();
+    let mut sdl = Sdl::new(canvas, &texture_creator, W, H);
 
     let tw = test_texture::TW as f32;
     let th = test_texture::TH as f32;
@@ -115,15 +112,9 @@ fn main() {
     let mut r = 0.0;
     let mut debug_overdraw = false;
     let mut draw_texels = true;
-    let mut bayer_dither = false;
-    const BAYER4X4_F: [[f32; 4]; 4] = [
-        // 4x4 ordered-dithering matrix
-        [0.0 / 16.0, 8.0 / 16.0, 1.0 / 16.0, 9.0 / 16.0],
-        [12.0 / 16.0, 4.0 / 16.0, 13.0 / 16.0, 5.0 / 16.0],
-        [3.0 / 16.0, 11.0 / 16.0, 2.0 / 16.0, 10.0 / 16.0],
-        [15.0 / 16.0, 7.0 / 16.0, 14.0 / 16.0, 6.0 / 16.0],
-    ];
+    let mut recorder = Recorder::new();
 
+    let scissor = Scissor::from_size(W, H);
     let frustum = rasterize::make_frustum(
         &[
             Vec2::ZERO,
@@ -163,7 +154,12 @@ fn main() {
                     // Keycode::D => debug_overdraw = !debug_overdraw,
                     Keycode::P => proj = !proj,
                     Keycode::T => draw_texels = !draw_texels,
-                    Keycode::B => bayer_dither = !bayer_dither,
+                    Keycode::F9 => println!("{}", recorder.toggle("capture", "gif").unwrap()),
+                    Keycode::F12 => {
+                        let path = image::numbered_path("screenshot", "pcx");
+                        fb.save_pcx(&path).unwrap();
+                        println!("screenshot: {:?}", path);
+                    }
                     _ => (),
                 },
                 _ => {}
@@ -207,14 +203,20 @@ fn main() {
         // }
 
         let start = Instant::now();
-        rasterize::rasterize::G_COUNT.store(0, std::sync::atomic::Ordering::SeqCst);
+        let mut stats = RasterStats::default();
         let mut num_texel = 0;
         for (p0, p1, p2, p3, bi) in quads.iter().cloned() {
             color = (color << 1) | (color >> (32 - 1));
             let poly_indexed = [p0, p1, p2, p3];
             let mut poly = poly_indexed
                 .iter()
-                .map(|(i, u, v)| (camera_rot * (points[*i as usize] - l), Vec2::new(*u, *v)))
+                .map(|(i, u, v)| {
+                    let size = Vec2::new(bitmaps[bi].width() as f32, bitmaps[bi].height() as f32);
+                    (
+                        camera_rot * (points[*i as usize] - l),
+                        Vec2::new(*u, *v) * size,
+                    )
+                })
                 .collect::<Vec<_>>();
 
             for p in frustum.iter() {
@@ -223,6 +225,7 @@ fn main() {
             }
 
             if poly.len() < 3 {
+                stats.clipped += 1;
                 continue;
             }
             let project_mat = glam::Mat4::perspective_rh(
@@ -256,65 +259,56 @@ fn main() {
                 0x808080, 0x80, 0x8000, 0x800000,
             ];
             // let clipped_polygon = vec![transform(p0), transform(p1), transform(p2), transform(p3)]
-            texpoly::draw_polygon(&poly[..], |x, y, z, u, v, aux| {
-                if !(x >= 0 && x < W as i32 && y >= 0 && y < H as i32) {
-                    return;
-                }
-
-                debug_assert!(x >= 0 && x < W as i32 && y >= 0 && y < H as i32);
-                let x = x as usize;
-                let y = y as usize;
-                let pixel_index = y * W as usize + x;
-
-                if !true {
-                    // let pixel = unsafe { pixels.get_unchecked_mut(pixel_index) };
-                    // if *pixel != blank && debug_overdraw {
-                    //     *pixel = duplicate;
-                    // } else {
-                    //     if draw_texels {
-                    //         let (ui, vi) = if bayer_dither {
-                    //             (
-                    //                 (u + BAYER4X4_F[y % 4][x % 4]) as usize,
-                    //                 (v + BAYER4X4_F[y % 4][x % 4]) as usize,
-                    //             )
-                    //         } else {
-                    //             (u as usize, v as usize)
-                    //         };
-                    //         let color = unsafe {
-                    //             bitmap.get_unchecked(
-                    //                 (vi % test_texture::TH) * test_texture::TW
-                    //                     + (ui % test_texture::TW),
-                    //             )
-                    //         };
-                    //         *pixel = color & 0xffffff;
-                    //     } else {
-                    //         *pixel = colors[aux as usize % colors.len()];
-                    //     }
-                    // }
-                } else {
-                    if z > fb.zbuffer[pixel_index] {
-                        return;
+            texpoly::draw_polygon(
+                &poly[..],
+                FillRule::Truncate,
+                &scissor,
+                &mut stats,
+                |x, y, z, u, v, aux| {
+                    debug_assert!(x >= 0 && x < W as i32 && y >= 0 && y < H as i32);
+                    let x = x as usize;
+                    let y = y as usize;
+                    let pixel_index = y * W as usize + x;
+
+                    if !true {
+                        // let pixel = unsafe { pixels.get_unchecked_mut(pixel_index) };
+                        // if *pixel != blank && debug_overdraw {
+                        //     *pixel = duplicate;
+                        // } else {
+                        //     if draw_texels {
+                        //         let (ui, vi) = (u as usize, v as usize);
+                        //         let color = unsafe {
+                        //             bitmap.get_unchecked(
+                        //                 (vi % test_texture::TH) * test_texture::TW
+                        //                     + (ui % test_texture::TW),
+                        //             )
+                        //         };
+                        //         *pixel = color & 0xffffff;
+                        //     } else {
+                        //         *pixel = colors[aux as usize % colors.len()];
+                        //     }
+                        // }
+                    } else {
+                        if z > fb.zbuffer[pixel_index] {
+                            return;
+                        }
+                        fb.zbuffer[pixel_index] = z;
+                        let zi = (31 + (z as usize / 2).saturating_sub(16)).min(47);
+                        let texel = bitmaps[bi].sample(u, v, Wrap::Repeat);
+                        fb.framebuffer[pixel_index] = mapping_table[zi][texel as usize];
+                        // debug_assert!(zi < palette::NUM_GAMMA_RAMP);
+                        // debug_assert!(pixel_index < fb.framebuffer.len());
+                        // debug_assert!(texel_index < bitmap.len());
+                        // unsafe {
+                        //     *fb.framebuffer.get_unchecked_mut(pixel_index) =
+                        //         *mapping_table.get_unchecked(zi).get_unchecked(
+                        //             *bitmaps.get_unchecked(bi).get_unchecked(texel_index) as usize,
+                        //         );
+                        // };
                     }
-                    fb.zbuffer[pixel_index] = z;
-                    let zi = (31 + (z as usize / 2).saturating_sub(16)).min(47);
-                    let u = u as usize % test_texture::TW;
-                    let v = v as usize % test_texture::TH;
-                    let texel_index = u + v * test_texture::TW;
-
-                    fb.framebuffer[pixel_index] =
-                        mapping_table[zi][bitmaps[bi][texel_index as usize] as usize];
-                    // debug_assert!(zi < palette::NUM_GAMMA_RAMP);
-                    // debug_assert!(pixel_index < fb.framebuffer.len());
-                    // debug_assert!(texel_index < bitmap.len());
-                    // unsafe {
-                    //     *fb.framebuffer.get_unchecked_mut(pixel_index) =
-                    //         *mapping_table.get_unchecked(zi).get_unchecked(
-                    //             *bitmaps.get_unchecked(bi).get_unchecked(texel_index) as usize,
-                    //         );
-                    // };
-                }
-                num_texel += 1;
-            });
+                    num_texel += 1;
+                },
+            );
         }
 
         let dt = start.elapsed();
@@ -325,6 +319,7 @@ fn main() {
             num_texel as f32 * 1e-6 / dt.as_secs_f32(),
             (dt.as_secs_f32() / num_texel as f32) * 2e9
         );
+        println!("{}", stats);
         // texture
         //     .update(
         //         None,
@@ -332,8 +327,7 @@ fn main() {
         //         4 * W as usize,
         //     )
         //     .unwrap();
-        fb.upload(&mut texture);
-        canvas.copy(&texture, None, None).unwrap();
-        canvas.present();
+        fb.present(&mut sdl).unwrap();
+        recorder.capture(&fb).unwrap();
     }
 }
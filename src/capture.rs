@@ -0,0 +1,597 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    image,
+    palette::{Framebuffer, NUM_COLORS},
+    present::Present,
+    quantize::{PaletteBuilder, Quantizer},
+};
+
+/// Animated GIF with a global color table, looping forever. Frames are palette indices; frames
+/// with a different palette get their own color table.
+pub struct GifEncoder<W: Write> {
+    writer: W,
+    width: u16,
+    height: u16,
+    palette: [u32; NUM_COLORS],
+    /// Time between frames in 1/100 s.
+    delay: u16,
+}
+
+impl<W: Write> GifEncoder<W> {
+    pub fn new(
+        mut writer: W,
+        width: u32,
+        height: u32,
+        palette: &[u32; NUM_COLORS],
+        delay: u16,
+    ) -> io::Result<Self> {
+        let (width, height) = (width as u16, height as u16);
+        writer.write_all(b"GIF89a")?;
+        writer.write_all(&width.to_le_bytes())?;
+        writer.write_all(&height.to_le_bytes())?;
+        // global color table of 256 entries, 8 bit color resolution
+        writer.write_all(&[0xf7, 0, 0])?;
+        write_color_table(&mut writer, palette)?;
+        // NETSCAPE2.0 application extension, loop count 0 is forever
+        writer.write_all(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+        Ok(GifEncoder {
+            writer,
+            width,
+            height,
+            palette: *palette,
+            delay,
+        })
+    }
+
+    pub fn write_frame(&mut self, indices: &[u8], palette: &[u32; NUM_COLORS]) -> io::Result<()> {
+        assert_eq!(indices.len(), self.width as usize * self.height as usize);
+        let w = &mut self.writer;
+        // graphic control extension: no disposal, no transparency
+        w.write_all(&[0x21, 0xf9, 4, 0])?;
+        w.write_all(&self.delay.to_le_bytes())?;
+        w.write_all(&[0, 0])?;
+        // image descriptor covering the whole screen
+        w.write_all(&[0x2c, 0, 0, 0, 0])?;
+        w.write_all(&self.width.to_le_bytes())?;
+        w.write_all(&self.height.to_le_bytes())?;
+        if *palette == self.palette {
+            w.write_all(&[0])?;
+        } else {
+            w.write_all(&[0x87])?;
+            write_color_table(w, palette)?;
+        }
+        w.write_all(&[8])?;
+        for block in lzw_encode(indices).chunks(255) {
+            w.write_all(&[block.len() as u8])?;
+            w.write_all(block)?;
+        }
+        w.write_all(&[0])
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.write_all(&[0x3b])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+fn write_color_table<W: Write>(writer: &mut W, palette: &[u32; NUM_COLORS]) -> io::Result<()> {
+    let table = palette
+        .iter()
+        .flat_map(|color| [*color as u8, (color >> 8) as u8, (color >> 16) as u8])
+        .collect::<Vec<_>>();
+    writer.write_all(&table)
+}
+
+// variable length LZW with 8 bit symbols as used by GIF, codes packed starting at the least
+// significant bit
+fn lzw_encode(indices: &[u8]) -> Vec<u8> {
+    const CLEAR: u16 = 256;
+    const END: u16 = 257;
+    const MAX_CODES: u16 = 4096;
+
+    let mut out = Vec::new();
+    let mut bits = 0u32;
+    let mut num_bits = 0;
+    let mut emit = |code: u16, size: u32, out: &mut Vec<u8>| {
+        bits |= (code as u32) << num_bits;
+        num_bits += size;
+        while num_bits >= 8 {
+            out.push(bits as u8);
+            bits >>= 8;
+            num_bits -= 8;
+        }
+    };
+
+    let mut table = HashMap::new();
+    let mut size = 9;
+    let mut next = END + 1;
+    emit(CLEAR, size, &mut out);
+    let mut indices = indices.iter();
+    if let Some(first) = indices.next() {
+        let mut prefix = *first as u16;
+        for index in indices {
+            if let Some(code) = table.get(&(prefix, *index)) {
+                prefix = *code;
+                continue;
+            }
+            emit(prefix, size, &mut out);
+            if next < MAX_CODES {
+                if next == 1 << size {
+                    size += 1;
+                }
+                table.insert((prefix, *index), next);
+                next += 1;
+            } else {
+                emit(CLEAR, size, &mut out);
+                table.clear();
+                size = 9;
+                next = END + 1;
+            }
+            prefix = *index as u16;
+        }
+        emit(prefix, size, &mut out);
+    }
+    emit(END, size, &mut out);
+    if num_bits > 0 {
+        out.push(bits as u8);
+    }
+    out
+}
+
+/// YUV4MPEG2 stream without chroma subsampling, from `r | g << 8 | b << 16` pixels. Samples
+/// have 16 bits, enough for 8 bit RGB to come back unchanged when decoded.
+pub struct Y4mEncoder<W: Write> {
+    writer: W,
+    width: u32,
+    height: u32,
+    planes: Vec<u8>,
+}
+
+impl<W: Write> Y4mEncoder<W> {
+    pub fn new(mut writer: W, width: u32, height: u32, fps: u32) -> io::Result<Self> {
+        writeln!(
+            writer,
+            "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444p16 XCOLORRANGE=FULL",
+            width, height, fps
+        )?;
+        Ok(Y4mEncoder {
+            writer,
+            width,
+            height,
+            planes: Vec::new(),
+        })
+    }
+
+    pub fn write_frame(&mut self, rgb: &[u32]) -> io::Result<()> {
+        let size = (self.width * self.height) as usize;
+        assert_eq!(rgb.len(), size);
+        // full range BT.601, little endian samples scaled from 0..255 to 0..65535
+        self.planes.resize(3 * 2 * size, 0);
+        for (i, color) in rgb.iter().enumerate() {
+            let [r, g, b, _] = color.to_le_bytes().map(|c| c as f32);
+            let y = 0.299 * r + 0.587 * g + 0.114 * b;
+            let u = (b - y) / 1.772 + 127.5;
+            let v = (r - y) / 1.402 + 127.5;
+            for (plane, sample) in [y, u, v].into_iter().enumerate() {
+                let sample = (sample * 257.0).round().clamp(0.0, 65535.0) as u16;
+                let pos = 2 * (plane * size + i);
+                self.planes[pos..pos + 2].copy_from_slice(&sample.to_le_bytes());
+            }
+        }
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&self.planes)
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+enum Encoder {
+    Gif(Box<GifEncoder<BufWriter<fs::File>>>),
+    Y4m(Y4mEncoder<BufWriter<fs::File>>),
+}
+
+enum Frame<'a> {
+    Indexed(&'a [u8], &'a [u32; NUM_COLORS]),
+    Rgb(&'a [u32]),
+}
+
+struct Recording {
+    path: PathBuf,
+    encoder: Option<Encoder>,
+    /// Maps truecolor frames to a palette built from the first one, for GIF.
+    quantizer: Option<(Quantizer, [u32; NUM_COLORS])>,
+    frames: u64,
+    max_frames: Option<u64>,
+}
+
+/// What `Recorder::toggle` did, for the caller to report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordEvent {
+    Started(PathBuf),
+    /// Number of frames written.
+    Stopped(u64),
+}
+
+impl std::fmt::Display for RecordEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordEvent::Started(path) => write!(f, "recording to {:?}", path),
+            RecordEvent::Stopped(frames) => write!(f, "recorded {} frames", frames),
+        }
+    }
+}
+
+/// Records frames to an animated GIF or a Y4M file, picked by the extension of the path. It
+/// can be started and stopped at any time and does nothing while stopped.
+pub struct Recorder {
+    pub fps: u32,
+    recording: Option<Recording>,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Recorder {
+            fps: 30,
+            recording: None,
+        }
+    }
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts recording to `path`, stopping after `max_frames` if given. A running recording is
+    /// finished first.
+    pub fn start<P>(&mut self, path: P, max_frames: Option<u64>) -> io::Result<()>
+    where
+        P: Into<PathBuf>,
+    {
+        self.stop()?;
+        let path = path.into();
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gif") | Some("y4m") => (),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("cannot record to {:?}, use .gif or .y4m", path),
+                ))
+            }
+        }
+        self.recording = Some(Recording {
+            path,
+            encoder: None,
+            quantizer: None,
+            frames: 0,
+            max_frames,
+        });
+        Ok(())
+    }
+
+    /// Finishes the file. Returns the number of frames written.
+    pub fn stop(&mut self) -> io::Result<u64> {
+        let Some(recording) = self.recording.take() else {
+            return Ok(0);
+        };
+        match recording.encoder {
+            Some(Encoder::Gif(encoder)) => {
+                encoder.finish()?;
+            }
+            Some(Encoder::Y4m(encoder)) => {
+                encoder.finish()?;
+            }
+            None => (),
+        }
+        Ok(recording.frames)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Stops a running recording or starts a new one to the next free
+    /// `<prefix>NNNNN.<extension>`, for a hotkey.
+    pub fn toggle<P>(&mut self, prefix: P, extension: &str) -> io::Result<RecordEvent>
+    where
+        P: AsRef<Path>,
+    {
+        if self.is_recording() {
+            Ok(RecordEvent::Stopped(self.stop()?))
+        } else {
+            let path = image::numbered_path(prefix, extension);
+            self.start(path.clone(), None)?;
+            Ok(RecordEvent::Started(path))
+        }
+    }
+
+    /// Adds the current contents of `fb`.
+    pub fn capture(&mut self, fb: &Framebuffer) -> io::Result<()> {
+        self.add_frame(
+            fb.width(),
+            fb.height(),
+            Frame::Indexed(&fb.framebuffer, fb.palette()),
+        )
+    }
+
+    /// Adds a truecolor frame. For GIF the palette is built from the first frame.
+    pub fn capture_rgb(&mut self, width: u32, height: u32, rgb: &[u32]) -> io::Result<()> {
+        self.add_frame(width, height, Frame::Rgb(rgb))
+    }
+
+    fn add_frame(&mut self, width: u32, height: u32, frame: Frame) -> io::Result<()> {
+        let fps = self.fps;
+        let Some(recording) = &mut self.recording else {
+            return Ok(());
+        };
+        let is_gif = recording.path.extension().is_some_and(|ext| ext == "gif");
+        if is_gif {
+            let (indices, palette) = match frame {
+                Frame::Indexed(indices, palette) => (indices.to_vec(), palette),
+                Frame::Rgb(rgb) => {
+                    let (quantizer, palette) = recording.quantizer.get_or_insert_with(|| {
+                        let palette = PaletteBuilder::new().add_image(rgb).build();
+                        (Quantizer::new(&palette), palette)
+                    });
+                    (quantizer.quantize(rgb), &*palette)
+                }
+            };
+            if recording.encoder.is_none() {
+                // GIF delays are in 1/100 s, most viewers don't go below 2
+                let delay = (100 / fps.max(1)).max(2) as u16;
+                let file = BufWriter::new(fs::File::create(&recording.path)?);
+                let encoder = GifEncoder::new(file, width, height, palette, delay)?;
+                recording.encoder = Some(Encoder::Gif(Box::new(encoder)));
+            }
+            if let Some(Encoder::Gif(encoder)) = &mut recording.encoder {
+                encoder.write_frame(&indices, palette)?;
+            }
+        } else {
+            let rgb = match frame {
+                Frame::Indexed(indices, palette) => {
+                    indices.iter().map(|i| palette[*i as usize]).collect()
+                }
+                Frame::Rgb(rgb) => rgb.to_vec(),
+            };
+            if recording.encoder.is_none() {
+                let file = BufWriter::new(fs::File::create(&recording.path)?);
+                recording.encoder = Some(Encoder::Y4m(Y4mEncoder::new(file, width, height, fps)?));
+            }
+            if let Some(Encoder::Y4m(encoder)) = &mut recording.encoder {
+                encoder.write_frame(&rgb)?;
+            }
+        }
+        recording.frames += 1;
+        if recording.max_frames == Some(recording.frames) {
+            self.stop()?;
+        }
+        Ok(())
+    }
+}
+
+impl Present for Recorder {
+    fn present(&mut self, width: u32, height: u32, rgb: &[u32]) -> io::Result<()> {
+        self.capture_rgb(width, height, rgb)
+    }
+}
+
+#[cfg(test)]
+fn decode_gif(data: &[u8]) -> (u16, u16, Vec<Vec<u8>>) {
+    fn lzw_decode(data: &[u8]) -> Vec<u8> {
+        let reset = |table: &mut Vec<Vec<u8>>| {
+            table.clear();
+            table.extend((0..=255).map(|i| vec![i]));
+            table.extend([vec![], vec![]]);
+        };
+        let mut table = Vec::new();
+        let mut size = 9;
+        let mut pos = 0;
+        let mut prev: Option<Vec<u8>> = None;
+        let mut out = Vec::new();
+        loop {
+            let code = (0..size).fold(0, |code, i| {
+                let bit = (data[(pos + i) / 8] >> ((pos + i) % 8)) & 1;
+                code | (bit as usize) << i
+            });
+            pos += size;
+            match code {
+                256 => {
+                    reset(&mut table);
+                    size = 9;
+                    prev = None;
+                    continue;
+                }
+                257 => break,
+                _ => (),
+            }
+            let entry = match (table.get(code), &prev) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(prev)) => [&prev[..], &prev[..1]].concat(),
+                (None, None) => panic!("invalid code {}", code),
+            };
+            if let Some(prev) = prev {
+                if table.len() < 4096 {
+                    table.push([&prev[..], &entry[..1]].concat());
+                }
+            }
+            if table.len() == 1 << size && size < 12 {
+                size += 1;
+            }
+            out.extend_from_slice(&entry);
+            prev = Some(entry);
+        }
+        out
+    }
+
+    assert_eq!(&data[..6], b"GIF89a");
+    let width = u16::from_le_bytes([data[6], data[7]]);
+    let height = u16::from_le_bytes([data[8], data[9]]);
+    let mut pos = 13 + 3 * NUM_COLORS;
+    let mut frames = Vec::new();
+    loop {
+        match data[pos] {
+            0x21 => {
+                pos += 2;
+                while data[pos] != 0 {
+                    pos += data[pos] as usize + 1;
+                }
+                pos += 1;
+            }
+            0x2c => {
+                if data[pos + 9] & 0x80 != 0 {
+                    pos += 3 * NUM_COLORS;
+                }
+                pos += 11;
+                let mut lzw = Vec::new();
+                while data[pos] != 0 {
+                    let len = data[pos] as usize;
+                    lzw.extend_from_slice(&data[pos + 1..pos + 1 + len]);
+                    pos += len + 1;
+                }
+                pos += 1;
+                frames.push(lzw_decode(&lzw));
+            }
+            0x3b => break,
+            other => panic!("unexpected block {:x}", other),
+        }
+    }
+    (width, height, frames)
+}
+
+#[test]
+fn test_record_gif() {
+    let palette = crate::test_fixtures::gray_palette();
+    // noisy enough to fill the code table and start over
+    let mut fb = Framebuffer::new(160, 120, &palette);
+    let mut seed = 1u32;
+    let mut frames = Vec::new();
+    let mut recorder = Recorder::new();
+    let path = std::env::temp_dir().join(format!("test_record_{}.gif", std::process::id()));
+    recorder.start(&path, Some(3)).unwrap();
+    for frame in 0..4 {
+        for (i, p) in fb.framebuffer.iter_mut().enumerate() {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            *p = if frame == 1 {
+                (i / 50) as u8
+            } else {
+                (seed >> 24) as u8
+            };
+        }
+        recorder.capture(&fb).unwrap();
+        frames.push(fb.framebuffer.clone());
+    }
+    // stopped by itself after 3 frames
+    assert!(!recorder.is_recording());
+    let data = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(decode_gif(&data), (160, 120, frames[..3].to_vec()));
+    assert_eq!(data[13 + 3 * 200..13 + 3 * 201], [200, 200, 200]);
+}
+
+#[cfg(test)]
+fn decode_y4m(data: &[u8]) -> (u32, u32, Vec<Vec<u32>>) {
+    let header_len = data.iter().position(|c| *c == b'\n').unwrap() + 1;
+    let header = std::str::from_utf8(&data[..header_len - 1]).unwrap();
+    assert!(header.contains(" C444p16 "));
+    let param = |tag| {
+        header
+            .split(' ')
+            .find_map(|param: &str| param.strip_prefix(tag))
+            .unwrap()
+            .parse::<u32>()
+            .unwrap()
+    };
+    let (width, height) = (param("W"), param("H"));
+    let size = (width * height) as usize;
+    let frames = data[header_len..]
+        .chunks(6 + 3 * 2 * size)
+        .map(|frame| {
+            assert_eq!(&frame[..6], b"FRAME\n");
+            let sample = |plane: usize, i: usize| {
+                let pos = 6 + 2 * (plane * size + i);
+                u16::from_le_bytes([frame[pos], frame[pos + 1]]) as f32 / 257.0
+            };
+            (0..size)
+                .map(|i| {
+                    let y = sample(0, i);
+                    let u = sample(1, i) - 127.5;
+                    let v = sample(2, i) - 127.5;
+                    let r = y + 1.402 * v;
+                    let b = y + 1.772 * u;
+                    let g = (y - 0.299 * r - 0.114 * b) / 0.587;
+                    [r, g, b].iter().enumerate().fold(0, |color, (c, value)| {
+                        color | (value.round().clamp(0.0, 255.0) as u32) << (8 * c)
+                    })
+                })
+                .collect()
+        })
+        .collect();
+    (width, height, frames)
+}
+
+#[test]
+fn test_y4m_round_trip() {
+    // every channel in steps of 15, 0 and 255 included
+    let rgb = (0..18 * 18 * 18)
+        .map(|i| (i % 18 * 15) | (i / 18 % 18 * 15) << 8 | (i / 324 * 15) << 16)
+        .collect::<Vec<u32>>();
+    let mut encoder = Y4mEncoder::new(Vec::new(), 18, 324, 30).unwrap();
+    encoder.write_frame(&rgb).unwrap();
+    let data = encoder.finish().unwrap();
+    assert_eq!(decode_y4m(&data), (18, 324, vec![rgb]));
+}
+
+#[test]
+fn test_record_y4m() {
+    let path = std::env::temp_dir().join(format!("test_record_{}.y4m", std::process::id()));
+    let mut recorder = Recorder::new();
+    assert!(recorder.start("capture.png", None).is_err());
+    recorder.capture_rgb(2, 1, &[0, 0]).unwrap();
+    recorder.start(&path, None).unwrap();
+    assert!(recorder.is_recording());
+    recorder.capture_rgb(2, 1, &[0xffffff, 0x0000ff]).unwrap();
+    recorder.present(2, 1, &[0x000000, 0x00ff00]).unwrap();
+    assert_eq!(recorder.stop().unwrap(), 2);
+    let data = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    let header = b"YUV4MPEG2 W2 H1 F30:1 Ip A1:1 C444p16 XCOLORRANGE=FULL\n";
+    assert_eq!(&data[..header.len()], header);
+    assert_eq!(
+        decode_y4m(&data),
+        (
+            2,
+            1,
+            vec![vec![0xffffff, 0x0000ff], vec![0x000000, 0x00ff00]]
+        )
+    );
+    // y of white is the largest sample
+    assert_eq!(data[header.len() + 6..header.len() + 8], [0xff, 0xff]);
+}
+
+#[test]
+fn test_toggle() {
+    let prefix = std::env::temp_dir().join(format!("test_toggle_{}_", std::process::id()));
+    let mut recorder = Recorder::new();
+    let RecordEvent::Started(path) = recorder.toggle(&prefix, "gif").unwrap() else {
+        panic!("not started");
+    };
+    assert!(recorder.is_recording());
+    recorder.present(2, 1, &[0x000000, 0x00ff00]).unwrap();
+    assert_eq!(
+        recorder.toggle(&prefix, "gif").unwrap(),
+        RecordEvent::Stopped(1)
+    );
+    assert!(!recorder.is_recording());
+    assert_eq!(RecordEvent::Stopped(1).to_string(), "recorded 1 frames");
+    fs::remove_file(&path).unwrap();
+}
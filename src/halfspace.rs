@@ -0,0 +1,325 @@
+use crate::{
+    rasterize::{RasterStats, Scissor},
+    texpoly::{Point, Varyings},
+};
+
+const TILE_SIZE: i32 = 8;
+
+// edge function E(x, y) = (to - from) x (p - from). Positive on the inside of clockwise triangles.
+#[derive(Debug, Clone, Copy)]
+struct Edge {
+    a: f32,
+    b: f32,
+    c: f32,
+    top_left: bool,
+}
+
+impl Edge {
+    fn new(from: (f32, f32), to: (f32, f32)) -> Self {
+        let a = from.1 - to.1;
+        let b = to.0 - from.0;
+        let c = -(a * from.0 + b * from.1);
+        // clockwise winding: top edges run left to right, left edges run upwards
+        let top_left = (from.1 == to.1 && to.0 > from.0) || to.1 < from.1;
+        Edge { a, b, c, top_left }
+    }
+    #[inline(always)]
+    fn eval(&self, x: f32, y: f32) -> f32 {
+        self.a * x + self.b * y + self.c
+    }
+    #[inline(always)]
+    fn inside(&self, e: f32) -> bool {
+        e > 0.0 || (e == 0.0 && self.top_left)
+    }
+}
+
+// attribute as a linear function of the screen position
+#[derive(Debug, Clone, Copy)]
+struct Gradient {
+    dx: f32,
+    dy: f32,
+    c: f32,
+}
+
+impl Gradient {
+    fn new(edges: &[Edge; 3], values: [f32; 3], inv_area: f32) -> Self {
+        let mut g = Gradient {
+            dx: 0.0,
+            dy: 0.0,
+            c: 0.0,
+        };
+        for (edge, value) in edges.iter().zip(values.iter()) {
+            g.dx += edge.a * value;
+            g.dy += edge.b * value;
+            g.c += edge.c * value;
+        }
+        g.dx *= inv_area;
+        g.dy *= inv_area;
+        g.c *= inv_area;
+        g
+    }
+    #[inline(always)]
+    fn eval(&self, x: f32, y: f32) -> f32 {
+        self.dx * x + self.dy * y + self.c
+    }
+}
+
+/// Edge function rasterizer. Takes the same points and fragment callback as
+/// `texpoly::draw_polygon`, always samples at pixel centers using the top-left rule and tests
+/// coverage per 8x8 tile before falling back to per-pixel tests.
+pub fn draw_polygon<F>(
+    points: &[Point],
+    scissor: &Scissor,
+    stats: &mut RasterStats,
+    mut fragment: F,
+) where
+    F: FnMut(i32, i32, f32, f32, f32, u32),
+{
+    draw_polygon_varyings(points, scissor, stats, |x, y, z, [u, v], aux| {
+        fragment(x, y, z, u, v, aux)
+    })
+}
+
+/// Like `draw_polygon`, but for any vertex type. `fragment` is called with
+/// `(x, y, z, attributes, aux)`.
+pub fn draw_polygon_varyings<V, F, const N: usize>(
+    points: &[V],
+    scissor: &Scissor,
+    stats: &mut RasterStats,
+    mut fragment: F,
+) where
+    V: Varyings<N>,
+    F: FnMut(i32, i32, f32, [f32; N], u32),
+{
+    stats.polygons += 1;
+    // only polygons without area, like the scanline rasterizer. Scissored or sub-pixel ones that
+    // draw nothing are not degenerate.
+    let area = match points.first() {
+        Some(p0) => {
+            let p0 = p0.position();
+            points
+                .windows(2)
+                .skip(1)
+                .map(|pair| {
+                    let (p1, p2) = (pair[0].position(), pair[1].position());
+                    (p1.0 - p0.0) * (p2.1 - p0.1) - (p1.1 - p0.1) * (p2.0 - p0.0)
+                })
+                .sum()
+        }
+        None => 0.0,
+    };
+    if area.abs() < 1e-6 {
+        stats.degenerate += 1;
+    }
+    for i in 1..points.len().saturating_sub(1) {
+        draw_triangle(
+            &points[0],
+            &points[i],
+            &points[i + 1],
+            i as u32 - 1,
+            scissor,
+            stats,
+            &mut fragment,
+        );
+    }
+}
+
+pub fn draw_triangle<V, F, const N: usize>(
+    p0: &V,
+    p1: &V,
+    p2: &V,
+    aux: u32,
+    scissor: &Scissor,
+    stats: &mut RasterStats,
+    mut fragment: F,
+) where
+    V: Varyings<N>,
+    F: FnMut(i32, i32, f32, [f32; N], u32),
+{
+    let (a0, a1, a2) = (p0.attributes(), p1.attributes(), p2.attributes());
+    let (p0, p1, p2) = (p0.position(), p1.position(), p2.position());
+    // edge i lies opposite of vertex i, so its edge function is the barycentric weight of vertex i
+    let edges = [
+        Edge::new((p1.0, p1.1), (p2.0, p2.1)),
+        Edge::new((p2.0, p2.1), (p0.0, p0.1)),
+        Edge::new((p0.0, p0.1), (p1.0, p1.1)),
+    ];
+    let area = edges[0].eval(p0.0, p0.1);
+    if area <= 0.0 {
+        // degenerate or back facing
+        return;
+    }
+    let inv_area = 1.0 / area;
+
+    let zi = [1.0 / p0.2, 1.0 / p1.2, 1.0 / p2.2];
+    let z_grad = Gradient::new(&edges, zi, inv_area);
+    let mut attribute_grads = [z_grad; N];
+    for (i, grad) in attribute_grads.iter_mut().enumerate() {
+        *grad = Gradient::new(
+            &edges,
+            [a0[i] * zi[0], a1[i] * zi[1], a2[i] * zi[2]],
+            inv_area,
+        );
+    }
+
+    // pixels whose centers may be covered
+    let xmin = ((p0.0.min(p1.0).min(p2.0) - 0.5).ceil() as i32).max(scissor.x0);
+    let xmax = ((p0.0.max(p1.0).max(p2.0) - 0.5).floor() as i32).min(scissor.x1 - 1);
+    let ymin = ((p0.1.min(p1.1).min(p2.1) - 0.5).ceil() as i32).max(scissor.y0);
+    let ymax = ((p0.1.max(p1.1).max(p2.1) - 0.5).floor() as i32).min(scissor.y1 - 1);
+
+    let mut num_fragments = 0;
+    let mut shade = |x: i32, y: i32| {
+        num_fragments += 1;
+        let (fx, fy) = (x as f32 + 0.5, y as f32 + 0.5);
+        let z = 1.0 / z_grad.eval(fx, fy);
+        fragment(x, y, z, attribute_grads.map(|g| g.eval(fx, fy) * z), aux);
+    };
+
+    let mut tile_y = ymin.div_euclid(TILE_SIZE) * TILE_SIZE;
+    while tile_y <= ymax {
+        let mut tile_x = xmin.div_euclid(TILE_SIZE) * TILE_SIZE;
+        while tile_x <= xmax {
+            // sample points of the tile corners
+            let x0 = tile_x as f32 + 0.5;
+            let x1 = (tile_x + TILE_SIZE - 1) as f32 + 0.5;
+            let y0 = tile_y as f32 + 0.5;
+            let y1 = (tile_y + TILE_SIZE - 1) as f32 + 0.5;
+
+            let mut reject = false;
+            let mut accept = true;
+            for edge in edges.iter() {
+                let num_inside = [(x0, y0), (x1, y0), (x0, y1), (x1, y1)]
+                    .iter()
+                    .filter(|(x, y)| edge.inside(edge.eval(*x, *y)))
+                    .count();
+                reject |= num_inside == 0;
+                accept &= num_inside == 4;
+            }
+
+            // accepted tiles can still cross the scissor rectangle
+            let rows = tile_y.max(ymin)..(tile_y + TILE_SIZE).min(ymax + 1);
+            let columns = tile_x.max(xmin)..(tile_x + TILE_SIZE).min(xmax + 1);
+            if accept {
+                for y in rows {
+                    for x in columns.clone() {
+                        shade(x, y);
+                    }
+                }
+            } else if !reject {
+                for y in rows {
+                    for x in columns.clone() {
+                        let (fx, fy) = (x as f32 + 0.5, y as f32 + 0.5);
+                        if edges.iter().all(|edge| edge.inside(edge.eval(fx, fy))) {
+                            shade(x, y);
+                        }
+                    }
+                }
+            }
+            tile_x += TILE_SIZE;
+        }
+        tile_y += TILE_SIZE;
+    }
+    stats.fragments += num_fragments;
+}
+
+#[test]
+fn test_halfspace_matches_scanline() {
+    use crate::rasterize::FillRule;
+    const S: usize = 64;
+    // scanline and fan triangulation only agree on attributes for planar polygons
+    let polys = [
+        vec![
+            (3.2, 2.7, 2.0, 0.0, 0.0),
+            (60.1, 20.3, 4.0, 64.0, 0.0),
+            (40.4, 61.9, 8.0, 64.0, 64.0),
+        ],
+        vec![
+            (4.5, 3.5, 5.0, 0.0, 0.0),
+            (60.5, 3.5, 5.0, 56.0, 0.0),
+            (60.5, 50.25, 5.0, 56.0, 46.75),
+            (4.5, 50.25, 5.0, 0.0, 46.75),
+        ],
+        vec![
+            (10.0, 10.0, 1.0, 0.0, 0.0),
+            (30.5, 10.0, 1.0, 1.0, 0.0),
+            (10.0, 30.5, 1.0, 0.0, 1.0),
+        ],
+    ];
+    for poly in polys.iter() {
+        let mut scanline = [[None; S]; S];
+        let mut stats = RasterStats::default();
+        let scissor = Scissor::from_size(S as u32, S as u32);
+        crate::texpoly::draw_polygon(
+            poly,
+            FillRule::TopLeft,
+            &scissor,
+            &mut stats,
+            |x, y, z, u, v, _| {
+                scanline[y as usize][x as usize] = Some((z, u, v));
+            },
+        );
+        let mut halfspace = [[None; S]; S];
+        draw_polygon(poly, &scissor, &mut stats, |x, y, z, u, v, _| {
+            assert!(halfspace[y as usize][x as usize].is_none());
+            halfspace[y as usize][x as usize] = Some((z, u, v));
+        });
+        for (line_s, line_h) in scanline.iter().zip(halfspace.iter()) {
+            for (s, h) in line_s.iter().zip(line_h.iter()) {
+                match (s, h) {
+                    (None, None) => (),
+                    (Some(s), Some(h)) => {
+                        assert!((s.0 - h.0).abs() < 1e-3 * s.0);
+                        assert!((s.1 - h.1).abs() < 1e-2);
+                        assert!((s.2 - h.2).abs() < 1e-2);
+                    }
+                    _ => panic!("coverage differs: {:?} {:?}", s, h),
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_halfspace_degenerate() {
+    use crate::rasterize::FillRule;
+    let scissor = Scissor::from_size(16, 16);
+    let degenerate = |poly: &[Point]| {
+        let mut scanline = RasterStats::default();
+        crate::texpoly::draw_polygon(
+            poly,
+            FillRule::TopLeft,
+            &scissor,
+            &mut scanline,
+            |_, _, _, _, _, _| (),
+        );
+        let mut halfspace = RasterStats::default();
+        draw_polygon(poly, &scissor, &mut halfspace, |_, _, _, _, _, _| ());
+        (scanline.degenerate, halfspace.degenerate)
+    };
+    // all points on a line
+    assert_eq!(
+        degenerate(&[
+            (1.0, 5.0, 1.0, 0.0, 0.0),
+            (5.0, 5.0, 1.0, 0.0, 0.0),
+            (9.0, 5.0, 1.0, 0.0, 0.0),
+        ]),
+        (1, 1)
+    );
+    // below the scissor rectangle
+    assert_eq!(
+        degenerate(&[
+            (2.0, 20.0, 1.0, 0.0, 0.0),
+            (12.0, 20.0, 1.0, 0.0, 0.0),
+            (2.0, 30.0, 1.0, 0.0, 0.0),
+        ]),
+        (0, 0)
+    );
+    // between pixel centers, the scanline rasterizer counts it for having no rows
+    let (_, halfspace) = degenerate(&[
+        (2.6, 2.6, 1.0, 0.0, 0.0),
+        (2.9, 2.6, 1.0, 0.0, 0.0),
+        (2.6, 2.9, 1.0, 0.0, 0.0),
+    ]);
+    assert_eq!(halfspace, 0);
+}
@@ -0,0 +1,274 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Binary PPM (P6) of `rgb` pixels, `r | g << 8 | b << 16` row after row.
+pub fn write_ppm<P>(path: P, width: u32, height: u32, rgb: &[u32]) -> io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    assert_eq!(rgb.len(), (width * height) as usize);
+    let mut data = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    data.extend(rgb.iter().flat_map(|color| {
+        let [r, g, b, _] = color.to_le_bytes();
+        [r, g, b]
+    }));
+    fs::File::create(path)?.write_all(&data)
+}
+
+/// Uncompressed 24 bit BMP of `rgb` pixels, `r | g << 8 | b << 16` row after row.
+pub fn write_bmp<P>(path: P, width: u32, height: u32, rgb: &[u32]) -> io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    assert_eq!(rgb.len(), (width * height) as usize);
+    // rows are stored bottom up as b, g, r and padded to 4 bytes
+    let pitch = (width * 3).next_multiple_of(4);
+    let offset = 14 + 40;
+    let size = offset + pitch * height;
+    let mut data = Vec::with_capacity(size as usize);
+    data.extend(b"BM");
+    data.extend(size.to_le_bytes());
+    data.extend([0; 4]);
+    data.extend(offset.to_le_bytes());
+    // BITMAPINFOHEADER: size, width, height, planes, bits per pixel, no compression, image size,
+    // 72 dpi and no color table
+    data.extend(40u32.to_le_bytes());
+    data.extend(width.to_le_bytes());
+    data.extend(height.to_le_bytes());
+    data.extend(1u16.to_le_bytes());
+    data.extend(24u16.to_le_bytes());
+    data.extend(0u32.to_le_bytes());
+    data.extend((pitch * height).to_le_bytes());
+    data.extend(2835u32.to_le_bytes());
+    data.extend(2835u32.to_le_bytes());
+    data.extend([0; 8]);
+    for row in rgb.chunks(width as usize).rev() {
+        let start = data.len();
+        data.extend(row.iter().flat_map(|color| {
+            let [r, g, b, _] = color.to_le_bytes();
+            [b, g, r]
+        }));
+        data.resize(start + pitch as usize, 0);
+    }
+    fs::File::create(path)?.write_all(&data)
+}
+
+/// First of `<prefix>00000.<extension>`, `<prefix>00001.<extension>`, ... that doesn't exist
+/// yet, e.g. for screenshots.
+pub fn numbered_path<P>(prefix: P, extension: &str) -> PathBuf
+where
+    P: AsRef<Path>,
+{
+    (0..)
+        .map(|i| {
+            let mut path = prefix.as_ref().as_os_str().to_owned();
+            path.push(format!("{:05}.{}", i, extension));
+            PathBuf::from(path)
+        })
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+/// Reads a binary PPM with 8 bit channels, as written by `write_ppm`. Returns width, height and
+/// the pixels.
+pub fn read_ppm<P>(path: P) -> io::Result<(u32, u32, Vec<u32>)>
+where
+    P: AsRef<Path>,
+{
+    let data = fs::read(path)?;
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    // magic, width, height and maximum value, separated by whitespace and comments
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while fields.len() < 4 {
+        match data.get(pos) {
+            Some(b'#') => {
+                while data.get(pos).is_some_and(|c| *c != b'\n') {
+                    pos += 1;
+                }
+            }
+            Some(c) if c.is_ascii_whitespace() => pos += 1,
+            Some(_) => {
+                let start = pos;
+                while data.get(pos).is_some_and(|c| !c.is_ascii_whitespace()) {
+                    pos += 1;
+                }
+                fields.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+            }
+            None => return Err(invalid("truncated ppm header")),
+        }
+    }
+    // a single whitespace character ends the header
+    pos += 1;
+    if fields[0] != "P6" || fields[3] != "255" {
+        return Err(invalid("only binary ppm with 8 bit channels is supported"));
+    }
+    let width = fields[1].parse().map_err(|_| invalid("invalid width"))?;
+    let height = fields[2].parse().map_err(|_| invalid("invalid height"))?;
+    let pixels = data
+        .get(pos..pos + (width * height * 3) as usize)
+        .ok_or_else(|| invalid("truncated ppm data"))?;
+    let rgb = pixels
+        .chunks(3)
+        .map(|c| c[0] as u32 | (c[1] as u32) << 8 | (c[2] as u32) << 16)
+        .collect();
+    Ok((width, height, rgb))
+}
+
+/// Result of comparing two images of the same size.
+#[derive(Debug, Clone)]
+pub struct ImageDiff {
+    pub width: u32,
+    pub height: u32,
+    /// Pixels with a channel off by more than the tolerance.
+    pub differing: usize,
+    /// Largest difference of a channel.
+    pub max_error: u32,
+    /// Differing pixels in red, the others in dimmed gray from the expected image.
+    pub image: Vec<u32>,
+}
+
+impl ImageDiff {
+    pub fn is_match(&self) -> bool {
+        self.differing == 0
+    }
+}
+
+/// Compares `actual` with `expected` per channel. A `tolerance` of 0 asks for exact matches.
+pub fn compare(
+    width: u32,
+    height: u32,
+    expected: &[u32],
+    actual: &[u32],
+    tolerance: u32,
+) -> ImageDiff {
+    assert_eq!(expected.len(), (width * height) as usize);
+    assert_eq!(actual.len(), expected.len());
+    let mut differing = 0;
+    let mut max_error = 0;
+    let image = expected
+        .iter()
+        .zip(actual.iter())
+        .map(|(expected, actual)| {
+            let error = (0..3)
+                .map(|c| {
+                    let a = (expected >> (8 * c)) & 0xff;
+                    let b = (actual >> (8 * c)) & 0xff;
+                    a.abs_diff(b)
+                })
+                .max()
+                .unwrap();
+            max_error = max_error.max(error);
+            if error > tolerance {
+                differing += 1;
+                0x0000ff
+            } else {
+                let gray =
+                    ((expected & 0xff) + ((expected >> 8) & 0xff) + ((expected >> 16) & 0xff)) / 12;
+                gray * 0x010101
+            }
+        })
+        .collect();
+    ImageDiff {
+        width,
+        height,
+        differing,
+        max_error,
+        image,
+    }
+}
+
+/// Compares `rgb` to the golden image at `path`, for use in tests.
+///
+/// With `UPDATE_GOLDEN` set in the environment the golden image is (re)written instead. On a
+/// mismatch `<path>.actual.ppm` and `<path>.diff.ppm` are written next to it before panicking.
+pub fn assert_golden<P>(path: P, width: u32, height: u32, rgb: &[u32], tolerance: u32)
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        write_ppm(path, width, height, rgb).unwrap();
+        return;
+    }
+    let sibling = |suffix: &str| {
+        let mut sibling = path.as_os_str().to_owned();
+        sibling.push(suffix);
+        PathBuf::from(sibling)
+    };
+    let (golden_width, golden_height, golden) = match read_ppm(path) {
+        Ok(golden) => golden,
+        Err(err) => {
+            write_ppm(sibling(".actual.ppm"), width, height, rgb).unwrap();
+            panic!(
+                "cannot read golden image {:?}: {}, rerun with UPDATE_GOLDEN=1 to create it",
+                path, err
+            );
+        }
+    };
+    assert_eq!(
+        (golden_width, golden_height),
+        (width, height),
+        "size differs from golden image {:?}",
+        path
+    );
+    let diff = compare(width, height, &golden, rgb, tolerance);
+    if !diff.is_match() {
+        write_ppm(sibling(".actual.ppm"), width, height, rgb).unwrap();
+        write_ppm(sibling(".diff.ppm"), width, height, &diff.image).unwrap();
+        panic!(
+            "{} pixels differ from golden image {:?} by more than {} (max {}), see .actual.ppm and .diff.ppm",
+            diff.differing, path, tolerance, diff.max_error
+        );
+    }
+}
+
+#[test]
+fn test_ppm_and_compare() {
+    let path = std::env::temp_dir().join(format!("test_ppm_{}.ppm", std::process::id()));
+    let rgb = [0x0000ff, 0x00ff00, 0xff0000, 0x102030, 0x000000, 0xffffff];
+    write_ppm(&path, 3, 2, &rgb).unwrap();
+    assert_eq!(read_ppm(&path).unwrap(), (3, 2, rgb.to_vec()));
+    fs::remove_file(&path).unwrap();
+
+    let mut actual = rgb;
+    actual[1] = 0x00fd00;
+    actual[4] = 0x000010;
+    assert!(compare(3, 2, &rgb, &rgb, 0).is_match());
+    let exact = compare(3, 2, &rgb, &actual, 0);
+    assert_eq!((exact.differing, exact.max_error), (2, 16));
+    assert_eq!(exact.image[1], 0x0000ff);
+    assert_eq!(exact.image[4], 0x0000ff);
+    assert_ne!(exact.image[0], 0x0000ff);
+    let tolerant = compare(3, 2, &rgb, &actual, 2);
+    assert_eq!(tolerant.differing, 1);
+}
+
+#[test]
+fn test_write_bmp() {
+    let prefix = std::env::temp_dir().join(format!("test_bmp_{}_", std::process::id()));
+    let path = numbered_path(&prefix, "bmp");
+    write_bmp(
+        &path,
+        3,
+        2,
+        &[0x0000ff, 0x00ff00, 0xff0000, 0x102030, 0x000000, 0xffffff],
+    )
+    .unwrap();
+    assert_ne!(numbered_path(&prefix, "bmp"), path);
+    let data = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(data.len(), 54 + 2 * 12);
+    assert_eq!(&data[..2], b"BM");
+    assert_eq!(data[2..6], (54u32 + 24).to_le_bytes());
+    assert_eq!(data[18..26], [3, 0, 0, 0, 2, 0, 0, 0]);
+    // bottom row first, padded to 12 bytes
+    assert_eq!(
+        data[54..66],
+        [0x10, 0x20, 0x30, 0, 0, 0, 0xff, 0xff, 0xff, 0, 0, 0]
+    );
+    assert_eq!(data[66..78], [0, 0, 0xff, 0, 0xff, 0, 0xff, 0, 0, 0, 0, 0]);
+}
@@ -1,48 +1,165 @@
+use std::path::Path;
+
 use glam::{IVec2, IVec3, Vec2, Vec3};
 
+use crate::{palette, texture::Texture};
+
 const SIZE_X: usize = 128;
 const SIZE_Y: usize = 1;
 const SIZE_Z: usize = 128;
 
+/// Light source that fades out linearly up to `radius`.
+#[derive(Debug, Clone, Copy)]
+pub struct PointLight {
+    pub pos: Vec3,
+    pub radius: f32,
+    pub intensity: f32,
+}
+
+/// Light value in 0..=1 for every point: `ambient` plus the contributions of all `lights`.
+pub fn vertex_lights(points: &[Vec3], ambient: f32, lights: &[PointLight]) -> Vec<f32> {
+    points
+        .iter()
+        .map(|p| {
+            let light = lights
+                .iter()
+                .map(|light| {
+                    (1.0 - light.pos.distance(*p) / light.radius).max(0.0) * light.intensity
+                })
+                .sum::<f32>();
+            (ambient + light).min(1.0)
+        })
+        .collect()
+}
+
+/// Texture of the polygons with a material index. Masked materials give the palette index that
+/// is see-through.
+#[derive(Debug, Clone, Copy)]
+pub struct Material {
+    pub texture: &'static str,
+    pub transparent: Option<u8>,
+}
+
+impl Material {
+    pub fn load(&self) -> Texture {
+        let path = Path::new(self.texture);
+        match self.transparent {
+            Some(transparent) => palette::read_pcx_masked(path, transparent),
+            None => palette::read_pcx(path),
+        }
+    }
+}
+
+/// Quad of `Blockmap::get_polygons`: point index and texture coordinates per corner, then the
+/// material.
+pub type Quad = (
+    (i32, f32, f32),
+    (i32, f32, f32),
+    (i32, f32, f32),
+    (i32, f32, f32),
+    usize,
+);
+
+/// Floor plan of the walkthrough level, for `Blockmap::add`.
+pub const EXAMPLE_FLOOR: [&[u8; 16]; 16] = [
+    b"................",
+    b"................",
+    b"................",
+    b"................",
+    b"................",
+    b".....1112111....",
+    b".....1..........",
+    b".....1..........",
+    b".....1111.......",
+    b"........1.......",
+    b"........111.....",
+    b"........1.......",
+    b"........2.......",
+    b"........1.......",
+    b".11111111.......",
+    b"................",
+];
+
+/// Lights of the walkthrough level.
+pub fn example_lights() -> [PointLight; 2] {
+    [
+        PointLight {
+            pos: Vec3::new(60.0, 0.0, 40.0),
+            radius: 150.0,
+            intensity: 0.9,
+        },
+        PointLight {
+            pos: Vec3::new(180.0, 0.0, 200.0),
+            radius: 200.0,
+            intensity: 0.75,
+        },
+    ]
+}
+
+pub const MATERIAL_WALL: usize = 0;
+pub const MATERIAL_FLOOR: usize = 1;
+pub const MATERIAL_GRATE: usize = 2;
+
+/// Materials of the `Blockmap` polygons, indexed by the `MATERIAL_*` constants.
+pub const MATERIALS: [Material; 3] = [
+    Material {
+        texture: "assets/wall01.pcx",
+        transparent: None,
+    },
+    Material {
+        texture: "assets/floor01.pcx",
+        transparent: None,
+    },
+    Material {
+        texture: "assets/grate01.pcx",
+        transparent: Some(255),
+    },
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cell {
+    Solid,
+    Open,
+    /// Open, with a grate across the middle.
+    Grate,
+}
+
 pub struct Blockmap {
-    bitmap: [[[bool; SIZE_X]; SIZE_Z]; SIZE_Y],
+    bitmap: [[[Cell; SIZE_X]; SIZE_Z]; SIZE_Y],
 }
 
 impl Blockmap {
     pub fn new() -> Self {
         Blockmap {
-            bitmap: [[[false; SIZE_X]; SIZE_Z]; SIZE_Y],
+            bitmap: [[[Cell::Solid; SIZE_X]; SIZE_Z]; SIZE_Y],
         }
     }
+
+    /// Fields are '1' for open cells, '2' for open cells with a grate and anything else for solid
+    /// ones.
     pub fn add(&mut self, pos: IVec3, fields: &[&[u8; 16]]) {
         let y = 0;
         for (z, line) in fields.iter().rev().enumerate() {
             for (x, c) in line.iter().enumerate() {
                 let x = (x as i32 + pos.x) as usize;
                 let z = (z as i32 + pos.z) as usize;
-                self.bitmap[y][z][x] = *c == b'1';
+                self.bitmap[y][z][x] = match c {
+                    b'1' => Cell::Open,
+                    b'2' => Cell::Grate,
+                    _ => Cell::Solid,
+                };
             }
         }
     }
 
-    pub fn get_polygons(
-        &self,
-    ) -> (
-        Vec<Vec3>,
-        Vec<(
-            (i32, f32, f32),
-            (i32, f32, f32),
-            (i32, f32, f32),
-            (i32, f32, f32),
-            usize,
-        )>,
-    ) {
+    pub fn get_polygons(&self) -> (Vec<Vec3>, Vec<Quad>) {
         let mut points = Vec::new();
         let mut polys = Vec::new();
         for y in 0..SIZE_Y {
             for z in 0..SIZE_Z {
                 for x in 0..SIZE_X {
-                    if !self.bitmap[y][z][x] {
+                    let cell = self.bitmap[y][z][x];
+                    if cell == Cell::Solid {
                         continue;
                     }
                     let offs = points.len() as i32;
@@ -62,47 +179,49 @@ impl Blockmap {
                         .map(|p| p + origin),
                     );
                     // #[rustfmt::skip]
-                    let tw = 256.0;
-                    let th = 256.0;
-                    if !self.bitmap[y][z + 1][x] {
+                    // normalized texture coordinates, scaled to the material's texture size
+                    // when drawing
+                    let tw = 1.0;
+                    let th = 1.0;
+                    if self.bitmap[y][z + 1][x] == Cell::Solid {
                         // back
                         polys.push((
                             (offs + 0, 0.0, 0.0),
                             (offs + 1, 0.0, th),
                             (offs + 2, tw, th),
                             (offs + 3, tw, 0.0),
-                            0,
+                            MATERIAL_WALL,
                         ));
                     }
-                    if !self.bitmap[y][z - 1][x] {
+                    if self.bitmap[y][z - 1][x] == Cell::Solid {
                         // front
                         polys.push((
                             (offs + 7, 0.0, 0.0),
                             (offs + 6, 0.0, th),
                             (offs + 5, tw, th),
                             (offs + 4, tw, 0.0),
-                            0,
+                            MATERIAL_WALL,
                         ));
                     }
 
-                    if !self.bitmap[y][z][x - 1] {
+                    if self.bitmap[y][z][x - 1] == Cell::Solid {
                         // left
                         polys.push((
                             (offs + 0, 0.0, 0.0),
                             (offs + 4, tw, 0.0),
                             (offs + 5, tw, th),
                             (offs + 1, 0.0, th),
-                            0,
+                            MATERIAL_WALL,
                         ));
                     }
-                    if !self.bitmap[y][z][x + 1] {
+                    if self.bitmap[y][z][x + 1] == Cell::Solid {
                         // right
                         polys.push((
                             (offs + 3, 0.0, 0.0),
                             (offs + 2, 0.0, th),
                             (offs + 6, tw, th),
                             (offs + 7, tw, 0.0),
-                            0,
+                            MATERIAL_WALL,
                         ));
                     }
                     // top
@@ -111,7 +230,7 @@ impl Blockmap {
                         (offs + 3, 0.0, th),
                         (offs + 7, tw, th),
                         (offs + 4, tw, 0.0),
-                        0,
+                        MATERIAL_WALL,
                     ));
                     // bottom
                     polys.push((
@@ -119,8 +238,45 @@ impl Blockmap {
                         (offs + 5, tw, 0.0),
                         (offs + 6, tw, th),
                         (offs + 2, 0.0, th),
-                        1,
+                        MATERIAL_FLOOR,
                     ));
+                    if cell == Cell::Grate {
+                        // across the corridor, i.e. in the plane between the open neighbors,
+                        // visible from both sides
+                        let offs = points.len() as i32;
+                        let along_x = self.bitmap[y][z][x - 1] != Cell::Solid
+                            || self.bitmap[y][z][x + 1] != Cell::Solid;
+                        let corners = if along_x {
+                            [
+                                Vec3::new(0.0, -10.0, -10.0),
+                                Vec3::new(0.0, 10.0, -10.0),
+                                Vec3::new(0.0, 10.0, 10.0),
+                                Vec3::new(0.0, -10.0, 10.0),
+                            ]
+                        } else {
+                            [
+                                Vec3::new(-10.0, -10.0, 0.0),
+                                Vec3::new(-10.0, 10.0, 0.0),
+                                Vec3::new(10.0, 10.0, 0.0),
+                                Vec3::new(10.0, -10.0, 0.0),
+                            ]
+                        };
+                        points.extend(corners.map(|p| p + origin));
+                        polys.push((
+                            (offs, 0.0, 0.0),
+                            (offs + 1, 0.0, th),
+                            (offs + 2, tw, th),
+                            (offs + 3, tw, 0.0),
+                            MATERIAL_GRATE,
+                        ));
+                        polys.push((
+                            (offs + 3, tw, 0.0),
+                            (offs + 2, tw, th),
+                            (offs + 1, 0.0, th),
+                            (offs, 0.0, 0.0),
+                            MATERIAL_GRATE,
+                        ));
+                    }
                 }
             }
         }
@@ -1,14 +1,28 @@
 #![feature(step_trait)]
 
+use std::ops::{Add, Mul, Sub};
+
 use glam::{Vec2, Vec3};
 
+pub mod capture;
+pub mod halfspace;
+pub mod image;
 pub mod level;
 pub mod math;
 pub mod palette;
+pub mod present;
+pub mod quantize;
 pub mod rasterize;
+pub mod render;
+pub mod scene;
 pub mod slope;
+pub mod span;
+#[cfg(all(unix, feature = "terminal"))]
+pub mod terminal;
 pub mod texpoly;
 pub mod texpoly_vec;
+pub mod texture;
+pub mod tranmap;
 
 pub mod test_texture {
     pub const TW: usize = 256;
@@ -52,6 +66,23 @@ pub mod test_texture {
         bitmap
     }
 }
+
+/// Fixtures shared by the unit tests.
+#[cfg(test)]
+pub(crate) mod test_fixtures {
+    use crate::palette::NUM_COLORS;
+
+    /// Palette index `i` is the gray `i, i, i`.
+    pub fn gray_palette() -> [u32; NUM_COLORS] {
+        std::array::from_fn(|i| i as u32 * 0x010101)
+    }
+
+    /// `rows` colormap rows that leave every palette index unchanged.
+    pub fn identity_colormap(rows: usize) -> Vec<[u8; NUM_COLORS]> {
+        vec![std::array::from_fn(|i| i as u8); rows]
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Plane {
     pub normal: Vec3,
@@ -94,7 +125,15 @@ fn test_plane() {
     println!("{:?}", p);
 }
 
-pub fn clip_polygon(plane: Plane, points: &[(Vec3, Vec2)]) -> Vec<(Vec3, Vec2)> {
+/// Vertex attributes that are carried along when clipping, e.g. texture coordinates.
+pub trait ClipAttributes:
+    Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f32, Output = Self>
+{
+}
+impl<T> ClipAttributes for T where T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>
+{}
+
+pub fn clip_polygon<A: ClipAttributes>(plane: Plane, points: &[(Vec3, A)]) -> Vec<(Vec3, A)> {
     let mut out = Vec::new();
 
     for i in 0..points.len() {
@@ -129,7 +168,7 @@ pub fn clip_polygon(plane: Plane, points: &[(Vec3, Vec2)]) -> Vec<(Vec3, Vec2)>
     out
 }
 
-pub fn clip_polygon_inplace(plane: Plane, points: &mut Vec<(Vec3, Vec2)>) {
+pub fn clip_polygon_inplace<A: ClipAttributes>(plane: Plane, points: &mut Vec<(Vec3, A)>) {
     // explicitly store copy of the first point so we can close the loop on the
     // last segment even if the point is removed.
     if points.len() < 3 {
@@ -227,9 +266,6 @@ where
             Vec3::ZERO,
         ));
     }
-    println!("frustum: {:?}", res);
-    // panic!();
-
     for p in res.iter_mut() {
         p.normal.z *= -1.0;
     }
@@ -2,10 +2,13 @@
 use std::{collections::VecDeque, fmt::Debug};
 
 use rasterize::{
+    capture::Recorder,
+    image,
+    present::{Present, Sdl},
     rasterize::{rasterize_triangle, Slope},
     slope::SlopeData,
 };
-use sdl2::{event::Event, keyboard::Keycode, pixels::PixelFormatEnum};
+use sdl2::{event::Event, keyboard::Keycode};
 
 // type Point = [i32; 5];
 type Point = (f32, f32, f32, f32, f32);
@@ -70,7 +73,7 @@ fn main() {
         .map_err(|e| e.to_string())
         .unwrap();
 
-    let mut canvas = window
+    let canvas = window
         .into_canvas()
         .software()
         .build()
@@ -78,20 +81,15 @@ fn main() {
         .unwrap();
 
     let texture_creator = canvas.texture_creator();
-    let mut texture = texture_creator
-        .create_texture(
-            PixelFormatEnum::ABGR8888,
-            sdl2::render::TextureAccess::Streaming,
-            W,
-            H,
-        )
-        .unwrap();
+    let mut sdl = Sdl::new(canvas, &texture_creator, W, H);
 
     let mut triangles = vec![
         ([10.0, 10.0], [20.0, 100.0], [90.0, 50.0]),
         // ([20, 10], [20, 100], [90, 50]),
     ];
     let mut new_triangle = VecDeque::new();
+    let mut screenshot = false;
+    let mut recorder = Recorder::new();
     'mainloop: loop {
         for event in sdl_context.event_pump().unwrap().poll_iter() {
             match event {
@@ -108,6 +106,14 @@ fn main() {
                         // new_triangle.clear();
                     }
                 }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => println!("{}", recorder.toggle("capture", "y4m").unwrap()),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } => screenshot = true,
                 _ => {}
             }
         }
@@ -137,14 +143,13 @@ fn main() {
             });
         }
 
-        texture
-            .update(
-                None,
-                unsafe { std::mem::transmute::<&[u32], &[u8]>(&pixels) },
-                4 * W as usize,
-            )
-            .unwrap();
-        canvas.copy(&texture, None, None).unwrap();
-        canvas.present();
+        if screenshot {
+            let path = image::numbered_path("screenshot", "ppm");
+            image::write_ppm(&path, W, H, &pixels).unwrap();
+            println!("screenshot: {:?}", path);
+            screenshot = false;
+        }
+        sdl.present(W, H, &pixels).unwrap();
+        recorder.present(W, H, &pixels).unwrap();
     }
 }
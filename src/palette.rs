@@ -1,12 +1,12 @@
-use std::{fs, path::Path};
+use std::{fs, io, ops::RangeInclusive, path::Path};
 
-use sdl2::{rect::Rect, render::Texture};
+use crate::{image, present::Present, quantize::Quantizer, texture::Texture};
 
 pub const NUM_COLORS: usize = 256;
 pub const NUM_GAMMA_RAMP: usize = 64;
 pub const NUM_ROWS: usize = 320;
 
-pub fn read_pcx<P>(path: P) -> Vec<u8>
+pub fn read_pcx<P>(path: P) -> Texture
 where
     P: AsRef<Path>,
 {
@@ -19,7 +19,39 @@ where
     for line in buf.chunks_mut(width) {
         pcx_reader.next_row_paletted(line);
     }
-    buf
+    Texture::new(width as u32, height as u32, buf)
+}
+
+/// Like `read_pcx`, for masked textures: texels with palette index `transparent` are not drawn.
+pub fn read_pcx_masked<P>(path: P, transparent: u8) -> Texture
+where
+    P: AsRef<Path>,
+{
+    read_pcx(path).with_transparent(transparent)
+}
+
+/// Writes palette indices, `width` per row, as a PCX file with `palette` appended.
+pub fn write_pcx<P>(
+    path: P,
+    width: u32,
+    height: u32,
+    indices: &[u8],
+    palette: &[u32; NUM_COLORS],
+) -> io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    assert_eq!(indices.len(), (width * height) as usize);
+    let mut writer =
+        pcx::WriterPaletted::create_file(path, (width as u16, height as u16), (300, 300))?;
+    for row in indices.chunks(width as usize) {
+        writer.write_row(row)?;
+    }
+    let palette_raw = palette
+        .iter()
+        .flat_map(|color| [*color as u8, (color >> 8) as u8, (color >> 16) as u8])
+        .collect::<Vec<_>>();
+    writer.write_palette(&palette_raw)
 }
 
 pub fn read_colormap() -> ([u32; NUM_COLORS], [[u8; NUM_COLORS]; NUM_ROWS]) {
@@ -44,6 +76,112 @@ pub fn read_colormap() -> ([u32; NUM_COLORS], [[u8; NUM_COLORS]; NUM_ROWS]) {
     (palette, mapping_table)
 }
 
+/// Picks the colormap row for a light value and a distance. Rows `bright_row..=dark_row` of the
+/// colormap go from full brightness to black. Light is added in row space like in Doom: the
+/// vertex light picks the start row, which is darkened by distance beyond `fade_start`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightModel {
+    pub bright_row: usize,
+    pub dark_row: usize,
+    pub fade_start: f32,
+    /// Colormap rows per unit of distance beyond `fade_start`.
+    pub fade_rate: f32,
+    /// Limit of the distance fade in rows, so far away surfaces don't turn black.
+    pub max_fade: usize,
+}
+
+impl Default for LightModel {
+    fn default() -> Self {
+        LightModel {
+            bright_row: 31,
+            dark_row: NUM_GAMMA_RAMP - 1,
+            fade_start: 32.0,
+            fade_rate: 0.5,
+            max_fade: 16,
+        }
+    }
+}
+
+impl LightModel {
+    /// Colormap row for `light` in 0..=1 at distance `z`.
+    #[inline(always)]
+    pub fn row(&self, light: f32, z: f32) -> usize {
+        let range = (self.dark_row - self.bright_row) as f32;
+        let darken = ((1.0 - light) * range).max(0.0) as usize;
+        let fade = (((z - self.fade_start) * self.fade_rate).max(0.0) as usize).min(self.max_fade);
+        (self.bright_row + darken + fade).min(self.dark_row)
+    }
+}
+
+/// Settings for building a light ramp colormap from any palette, instead of reading
+/// `assets/COLORMAP.PCX`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColormapSpec {
+    /// Number of rows, from unchanged colors in the first row to `fade_color` in the last one.
+    pub levels: usize,
+    /// Black fades to darkness, any other color fades into fog.
+    pub fade_color: u32,
+    /// Palette indices that keep their color in every row, e.g. lamps or lava. Other colors never
+    /// map to them.
+    pub fullbright: Vec<RangeInclusive<u8>>,
+}
+
+impl Default for ColormapSpec {
+    fn default() -> Self {
+        ColormapSpec {
+            levels: 32,
+            fade_color: 0,
+            fullbright: Vec::new(),
+        }
+    }
+}
+
+impl ColormapSpec {
+    pub fn generate(&self, palette: &[u32; NUM_COLORS]) -> Vec<[u8; NUM_COLORS]> {
+        assert!(self.levels >= 2);
+        let fullbright = self
+            .fullbright
+            .iter()
+            .flat_map(|range| range.clone())
+            .collect::<Vec<_>>();
+        let quantizer = Quantizer::excluding(palette, &fullbright);
+        (0..self.levels)
+            .map(|level| {
+                let t = level as f32 / (self.levels - 1) as f32;
+                let fade = |color: u32, shift: u32| {
+                    let color = ((color >> shift) & 0xff) as f32;
+                    let target = ((self.fade_color >> shift) & 0xff) as f32;
+                    ((color + (target - color) * t).round() as u32) << shift
+                };
+                let rgb = palette
+                    .iter()
+                    .map(|color| fade(*color, 0) | fade(*color, 8) | fade(*color, 16))
+                    .collect::<Vec<_>>();
+                let mut row = [0u8; NUM_COLORS];
+                row.copy_from_slice(&quantizer.quantize(&rgb));
+                for i in fullbright.iter() {
+                    row[*i as usize] = *i;
+                }
+                row
+            })
+            .collect()
+    }
+
+    /// Light model for the generated rows, darkening by distance at the same pace as the default
+    /// one relative to the number of levels.
+    pub fn light_model(&self) -> LightModel {
+        let default = LightModel::default();
+        let scale = (self.levels - 1) as f32 / (default.dark_row - default.bright_row) as f32;
+        LightModel {
+            bright_row: 0,
+            dark_row: self.levels - 1,
+            fade_rate: default.fade_rate * scale,
+            max_fade: (default.max_fade as f32 * scale) as usize,
+            ..default
+        }
+    }
+}
+
 pub struct Framebuffer {
     width: u32,
     height: u32,
@@ -64,25 +202,83 @@ impl Framebuffer {
             zbuffer: vec![f32::MAX; (width * height) as usize],
         }
     }
-    pub fn upload(&mut self, texture: &mut Texture) {
+    /// Hands the frame to `backend`, looked up in the palette.
+    pub fn present<P>(&mut self, backend: &mut P) -> io::Result<()>
+    where
+        P: Present + ?Sized,
+    {
         for (p, p_rgb) in self.framebuffer.iter().zip(self.framebuffer_rgb.iter_mut()) {
             *p_rgb = self.palette[*p as usize];
         }
-        texture
-            .update(
-                Some(Rect::new(0, 0, self.width, self.height)),
-                unsafe { std::mem::transmute::<&[u32], &[u8]>(&self.framebuffer_rgb) },
-                self.width as usize * 4,
-            )
-            .unwrap();
+        backend.present(self.width, self.height, &self.framebuffer_rgb)
+    }
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    pub fn palette(&self) -> &[u32; NUM_COLORS] {
+        &self.palette
     }
     pub fn clear(&mut self) {
         self.framebuffer.fill(0);
         self.zbuffer.fill(f32::MAX);
     }
+    /// Palette indices together with the palette, readable by `read_pcx`.
+    pub fn save_pcx<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        write_pcx(
+            path,
+            self.width,
+            self.height,
+            &self.framebuffer,
+            &self.palette,
+        )
+    }
+    pub fn save_ppm<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        image::write_ppm(path, self.width, self.height, &self.to_rgb())
+    }
+    pub fn save_bmp<P>(&self, path: P) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        image::write_bmp(path, self.width, self.height, &self.to_rgb())
+    }
+    fn to_rgb(&self) -> Vec<u32> {
+        self.framebuffer
+            .iter()
+            .map(|p| self.palette[*p as usize])
+            .collect()
+    }
 }
 
+/// Nearest palette index for every color by linear search. `quantize::Quantizer` gives the same
+/// result much faster for more than a few colors.
 pub fn quantize(palette: &[u32; NUM_COLORS], rgb: &[u32]) -> Vec<u8> {
+    quantize_with(palette, rgb, None)
+}
+
+/// Like `quantize`, but never picks the palette indices in `exclude`, e.g. the transparent index
+/// of masked textures.
+pub fn quantize_excluding(palette: &[u32; NUM_COLORS], rgb: &[u32], exclude: &[u8]) -> Vec<u8> {
+    let mut excluded = [false; NUM_COLORS];
+    for i in exclude {
+        excluded[*i as usize] = true;
+    }
+    quantize_with(palette, rgb, Some(&excluded))
+}
+
+fn quantize_with(
+    palette: &[u32; NUM_COLORS],
+    rgb: &[u32],
+    excluded: Option<&[bool; NUM_COLORS]>,
+) -> Vec<u8> {
     let msd = |a, b| {
         let r = (a & 0xff) as i32 - (b & 0xff) as i32;
         let g = ((a >> 8) & 0xffu32) as i32 - ((b >> 8) & 0xffu32) as i32;
@@ -94,6 +290,9 @@ pub fn quantize(palette: &[u32; NUM_COLORS], rgb: &[u32]) -> Vec<u8> {
             let mut out = 0;
             let mut min = u32::MAX;
             for (i, p) in palette.iter().enumerate() {
+                if excluded.is_some_and(|excluded| excluded[i]) {
+                    continue;
+                }
                 let err = msd(rgb, p);
                 if err < min {
                     min = err;
@@ -104,3 +303,73 @@ pub fn quantize(palette: &[u32; NUM_COLORS], rgb: &[u32]) -> Vec<u8> {
         })
         .collect()
 }
+
+#[test]
+fn test_save_pcx() {
+    let mut palette = [0u32; NUM_COLORS];
+    for (i, color) in palette.iter_mut().enumerate() {
+        *color = ((i as u32) * 0x030507) & 0xffffff;
+    }
+    // odd width, PCX rows are padded to an even number of bytes
+    let mut fb = Framebuffer::new(5, 3, &palette);
+    for (i, p) in fb.framebuffer.iter_mut().enumerate() {
+        *p = (i * 17) as u8;
+    }
+    let path = std::env::temp_dir().join(format!("test_save_pcx_{}.pcx", std::process::id()));
+    fb.save_pcx(&path).unwrap();
+    let texture = read_pcx(&path);
+    assert_eq!((texture.width(), texture.height()), (5, 3));
+    assert_eq!(texture.texels(), &fb.framebuffer[..]);
+
+    let mut reader = pcx::Reader::new(fs::File::open(&path).unwrap()).unwrap();
+    for _ in 0..3 {
+        reader.next_row_paletted(&mut [0; 5]).unwrap();
+    }
+    let mut palette_raw = [0u8; 3 * NUM_COLORS];
+    reader.read_palette(&mut palette_raw).unwrap();
+    assert_eq!(
+        palette_raw[3 * 100..3 * 101],
+        palette[100].to_le_bytes()[..3]
+    );
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_light_model() {
+    let light = LightModel::default();
+    // fully lit it reproduces the old depth formula
+    for z in 0..200usize {
+        let old = (31 + (z / 2).saturating_sub(16)).min(47);
+        assert_eq!(light.row(1.0, z as f32), old, "z {}", z);
+    }
+    assert_eq!(light.row(0.0, 0.0), NUM_GAMMA_RAMP - 1);
+    assert!(light.row(0.25, 10.0) > light.row(0.75, 10.0));
+}
+
+#[test]
+fn test_generate_colormap() {
+    let palette = crate::test_fixtures::gray_palette();
+    let spec = ColormapSpec {
+        levels: 5,
+        ..Default::default()
+    };
+    let colormap = spec.generate(&palette);
+    assert_eq!(colormap.len(), 5);
+    assert!((0..NUM_COLORS).all(|i| colormap[0][i] as usize == i));
+    assert_eq!(colormap[2][200], 100);
+    assert!(colormap[4].iter().all(|i| *i == 0));
+    let light = spec.light_model();
+    assert_eq!(light.row(1.0, 0.0), 0);
+    assert_eq!(light.row(0.0, 0.0), 4);
+
+    // fog, with the brightest colors kept as they are
+    let spec = ColormapSpec {
+        levels: 3,
+        fade_color: 0xffffff,
+        fullbright: vec![250..=255],
+    };
+    let colormap = spec.generate(&palette);
+    assert_eq!(colormap[1][0], 128);
+    assert!(colormap[2][..250].iter().all(|i| *i == 249));
+    assert_eq!(colormap[2][250..], [250, 251, 252, 253, 254, 255]);
+}
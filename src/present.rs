@@ -0,0 +1,282 @@
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use crate::image::write_ppm;
+
+/// Takes finished frames, e.g. from `Framebuffer::present`. Pixels are `r | g << 8 | b << 16`,
+/// row after row.
+pub trait Present {
+    fn present(&mut self, width: u32, height: u32, rgb: &[u32]) -> io::Result<()>;
+}
+
+/// Keeps the last frame in memory, for tests and runs without a window.
+#[derive(Debug, Clone, Default)]
+pub struct Headless {
+    pub width: u32,
+    pub height: u32,
+    pub rgb: Vec<u32>,
+    /// Number of frames presented so far.
+    pub frames: u64,
+}
+
+impl Headless {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Present for Headless {
+    fn present(&mut self, width: u32, height: u32, rgb: &[u32]) -> io::Result<()> {
+        self.width = width;
+        self.height = height;
+        self.rgb.clear();
+        self.rgb.extend_from_slice(rgb);
+        self.frames += 1;
+        Ok(())
+    }
+}
+
+/// Writes every frame to a numbered PPM file, `<prefix>00000.ppm` and so on.
+#[derive(Debug, Clone)]
+pub struct FileDump {
+    prefix: PathBuf,
+    pub frames: u64,
+}
+
+impl FileDump {
+    pub fn new<P>(prefix: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        FileDump {
+            prefix: prefix.into(),
+            frames: 0,
+        }
+    }
+
+    /// File of frame `frame`.
+    pub fn path(&self, frame: u64) -> PathBuf {
+        let mut path = self.prefix.clone().into_os_string();
+        path.push(format!("{:05}.ppm", frame));
+        path.into()
+    }
+}
+
+impl Present for FileDump {
+    fn present(&mut self, width: u32, height: u32, rgb: &[u32]) -> io::Result<()> {
+        write_ppm(self.path(self.frames), width, height, rgb)?;
+        self.frames += 1;
+        Ok(())
+    }
+}
+
+/// Draws frames with 24 bit ANSI colors, two pixels per character cell: the upper one is the
+/// foreground of `▀`, the lower one the background. With a `scale` above 1 every cell averages
+/// `scale` by `2 * scale` pixels.
+pub struct Ansi<W: Write = io::Stdout> {
+    writer: W,
+    pub scale: u32,
+    out: String,
+}
+
+impl<W: Write> Ansi<W> {
+    pub fn new(writer: W, scale: u32) -> Self {
+        Ansi {
+            writer,
+            scale,
+            out: String::new(),
+        }
+    }
+
+    /// Character cells needed for a frame of `width` by `height` pixels.
+    pub fn cells(&self, width: u32, height: u32) -> (u32, u32) {
+        let scale = self.scale.max(1);
+        (width.div_ceil(scale), height.div_ceil(2 * scale))
+    }
+
+    pub fn writer(&self) -> &W {
+        &self.writer
+    }
+
+    // average of the pixels in the block at (x, y), black outside the frame
+    fn block(&self, width: u32, height: u32, rgb: &[u32], x: u32, y: u32) -> [u32; 3] {
+        let scale = self.scale.max(1);
+        let mut sum = [0; 3];
+        for py in y * scale..(y + 1) * scale {
+            for px in x * scale..(x + 1) * scale {
+                if px < width && py < height {
+                    let color = rgb[(py * width + px) as usize];
+                    for (c, sum) in sum.iter_mut().enumerate() {
+                        *sum += (color >> (8 * c)) & 0xff;
+                    }
+                }
+            }
+        }
+        sum.map(|sum| (sum + scale * scale / 2) / (scale * scale))
+    }
+}
+
+impl<W: Write> Present for Ansi<W> {
+    fn present(&mut self, width: u32, height: u32, rgb: &[u32]) -> io::Result<()> {
+        let (columns, rows) = self.cells(width, height);
+        self.out.clear();
+        // home the cursor, colors are only set when they change
+        self.out.push_str("\x1b[H");
+        for row in 0..rows {
+            let mut last = None;
+            for column in 0..columns {
+                let upper = self.block(width, height, rgb, column, 2 * row);
+                let lower = self.block(width, height, rgb, column, 2 * row + 1);
+                if last != Some((upper, lower)) {
+                    let [r0, g0, b0] = upper;
+                    let [r1, g1, b1] = lower;
+                    let _ = write!(
+                        self.out,
+                        "\x1b[38;2;{};{};{};48;2;{};{};{}m",
+                        r0, g0, b0, r1, g1, b1
+                    );
+                    last = Some((upper, lower));
+                }
+                self.out.push('▀');
+            }
+            self.out.push_str("\x1b[0m");
+            if row + 1 < rows {
+                self.out.push_str("\r\n");
+            }
+        }
+        self.writer.write_all(self.out.as_bytes())?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(feature = "sdl")]
+pub use self::sdl::Sdl;
+
+#[cfg(feature = "sdl")]
+mod sdl {
+    use sdl2::{
+        pixels::PixelFormatEnum,
+        render::{Canvas, Texture, TextureAccess, TextureCreator},
+        video::{Window, WindowContext},
+    };
+
+    use std::io;
+
+    use super::Present;
+
+    /// Streams frames into a texture that is stretched over the window.
+    pub struct Sdl<'a> {
+        pub canvas: Canvas<Window>,
+        texture: Texture<'a>,
+        bytes: Vec<u8>,
+    }
+
+    impl<'a> Sdl<'a> {
+        /// `texture_creator` has to come from `canvas`.
+        pub fn new(
+            canvas: Canvas<Window>,
+            texture_creator: &'a TextureCreator<WindowContext>,
+            width: u32,
+            height: u32,
+        ) -> Self {
+            let texture = texture_creator
+                .create_texture(
+                    PixelFormatEnum::ABGR8888,
+                    TextureAccess::Streaming,
+                    width,
+                    height,
+                )
+                .unwrap();
+            Sdl {
+                canvas,
+                texture,
+                bytes: Vec::new(),
+            }
+        }
+    }
+
+    impl Present for Sdl<'_> {
+        fn present(&mut self, width: u32, height: u32, rgb: &[u32]) -> io::Result<()> {
+            // ABGR8888 is r, g, b, a in memory, i.e. the little endian bytes of our pixels
+            self.bytes.clear();
+            self.bytes
+                .extend(rgb.iter().flat_map(|color| color.to_le_bytes()));
+            let rect = sdl2::rect::Rect::new(0, 0, width, height);
+            self.texture
+                .update(Some(rect), &self.bytes, width as usize * 4)
+                .map_err(|err| io::Error::other(err.to_string()))?;
+            self.canvas
+                .copy(&self.texture, rect, None)
+                .map_err(io::Error::other)?;
+            self.canvas.present();
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn test_file_dump() {
+    let prefix = std::env::temp_dir().join(format!("test_file_dump_{}_", std::process::id()));
+    let mut dump = FileDump::new(&prefix);
+    let mut headless = Headless::new();
+    for frame in [[0x0000ff, 0x00ff00], [0xff0000, 0x123456]] {
+        dump.present(2, 1, &frame).unwrap();
+        headless.present(2, 1, &frame).unwrap();
+    }
+    assert_eq!(headless.frames, 2);
+    assert_eq!(headless.rgb, [0xff0000, 0x123456]);
+    assert_eq!(
+        std::fs::read(dump.path(0)).unwrap(),
+        b"P6\n2 1\n255\n\xff\x00\x00\x00\xff\x00"
+    );
+    assert_eq!(
+        std::fs::read(dump.path(1)).unwrap(),
+        b"P6\n2 1\n255\n\x00\x00\xff\x56\x34\x12"
+    );
+    for frame in 0..2 {
+        std::fs::remove_file(dump.path(frame)).unwrap();
+    }
+}
+
+#[test]
+fn test_ansi() {
+    let mut ansi = Ansi::new(Vec::new(), 1);
+    // the odd last row has a black lower half
+    ansi.present(
+        2,
+        3,
+        &[0x0000ff, 0x0000ff, 0x00ff00, 0x00ff00, 0x102030, 0xffffff],
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(ansi.writer().clone()).unwrap(),
+        "\x1b[H\x1b[38;2;255;0;0;48;2;0;255;0m▀▀\x1b[0m\r\n\
+         \x1b[38;2;48;32;16;48;2;0;0;0m▀\x1b[38;2;255;255;255;48;2;0;0;0m▀\x1b[0m"
+    );
+
+    let mut ansi = Ansi::new(Vec::new(), 2);
+    assert_eq!(ansi.cells(4, 4), (2, 1));
+    ansi.present(
+        4,
+        4,
+        &[
+            0x000000, 0x0000ff, 0x0, 0x0, //
+            0x0000ff, 0x0000ff, 0x0, 0x0, //
+            0x00ff00, 0x00ff00, 0x0, 0x0, //
+            0x00ff00, 0x00ff00, 0x0, 0x0, //
+        ],
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(ansi.writer().clone()).unwrap(),
+        "\x1b[H\x1b[38;2;191;0;0;48;2;0;255;0m▀\x1b[38;2;0;0;0;48;2;0;0;0m▀\x1b[0m"
+    );
+
+    // write errors reach the caller
+    let mut full = [0u8; 4];
+    let mut ansi = Ansi::new(&mut full[..], 1);
+    assert!(ansi.present(1, 1, &[0]).is_err());
+}
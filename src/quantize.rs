@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+
+use crate::{
+    palette::NUM_COLORS,
+    texture::{Dither, DitherKernel},
+};
+
+// the color cube is split into cells of 2^CELL_BITS values per channel
+const CELL_BITS: u32 = 4;
+const CELLS: usize = 256 >> CELL_BITS;
+
+#[inline(always)]
+fn channels(color: u32) -> [i32; 3] {
+    [
+        (color & 0xff) as i32,
+        ((color >> 8) & 0xff) as i32,
+        ((color >> 16) & 0xff) as i32,
+    ]
+}
+
+/// How `Quantizer::quantize_image` spreads the error of mapping pixels to the palette.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageDither {
+    /// Every pixel independently, which bands on smooth gradients.
+    None,
+    /// Error diffusion to the right and bottom neighbors.
+    FloydSteinberg,
+    /// Adds a threshold pattern of up to `spread` / 2 per channel before the lookup, which tiles
+    /// instead of crawling like error diffusion.
+    Ordered { kernel: DitherKernel, spread: i32 },
+}
+
+/// Nearest palette color lookup, with the same result as the linear search of
+/// `palette::quantize`. Each cell of the color cube keeps the palette entries that can be nearest
+/// to any color inside of it: those not farther from the cell than the farthest point of the
+/// cell is from the best entry. A lookup only compares against these, usually a handful.
+#[derive(Debug, Clone)]
+pub struct Quantizer {
+    palette: [[i32; 3]; NUM_COLORS],
+    /// Range of `candidates` per cell, indexed by `r + g * CELLS + b * CELLS²` of the cell.
+    cells: Vec<(u32, u32)>,
+    /// Palette indices in ascending order per cell, so ties resolve to the lowest index.
+    candidates: Vec<u8>,
+}
+
+impl Quantizer {
+    pub fn new(palette: &[u32; NUM_COLORS]) -> Self {
+        Self::excluding(palette, &[])
+    }
+
+    /// Never picks the palette indices in `exclude`, like `palette::quantize_excluding`.
+    pub fn excluding(palette: &[u32; NUM_COLORS], exclude: &[u8]) -> Self {
+        let mut entries = [[0i32; 3]; NUM_COLORS];
+        for (entry, color) in entries.iter_mut().zip(palette.iter()) {
+            *entry = channels(*color);
+        }
+        let included = (0..NUM_COLORS)
+            .filter(|i| !exclude.contains(&(*i as u8)))
+            .collect::<Vec<_>>();
+        assert!(!included.is_empty());
+
+        let size = 1 << CELL_BITS;
+        let mut cells = Vec::with_capacity(CELLS * CELLS * CELLS);
+        let mut candidates = Vec::new();
+        let mut min_dists = vec![0; NUM_COLORS];
+        for b in 0..CELLS as i32 {
+            for g in 0..CELLS as i32 {
+                for r in 0..CELLS as i32 {
+                    let lo = [r * size, g * size, b * size];
+                    let mut bound = i32::MAX;
+                    for i in included.iter() {
+                        let entry = &entries[*i];
+                        let mut min_dist = 0;
+                        let mut max_dist = 0;
+                        for c in 0..3 {
+                            let (lo, hi) = (lo[c], lo[c] + size - 1);
+                            let near = entry[c].clamp(lo, hi) - entry[c];
+                            let far = (entry[c] - lo).abs().max((entry[c] - hi).abs());
+                            min_dist += near * near;
+                            max_dist += far * far;
+                        }
+                        min_dists[*i] = min_dist;
+                        bound = bound.min(max_dist);
+                    }
+                    let start = candidates.len() as u32;
+                    candidates.extend(
+                        included
+                            .iter()
+                            .filter(|i| min_dists[**i] <= bound)
+                            .map(|i| *i as u8),
+                    );
+                    cells.push((start, candidates.len() as u32));
+                }
+            }
+        }
+        Quantizer {
+            palette: entries,
+            cells,
+            candidates,
+        }
+    }
+
+    /// Palette index closest to `rgb`.
+    #[inline]
+    pub fn nearest(&self, rgb: u32) -> u8 {
+        let color = channels(rgb);
+        let cell = |c: i32| (c >> CELL_BITS) as usize;
+        let (start, end) =
+            self.cells[cell(color[0]) + cell(color[1]) * CELLS + cell(color[2]) * CELLS * CELLS];
+        let mut out = 0;
+        let mut min = i32::MAX;
+        for i in self.candidates[start as usize..end as usize].iter() {
+            let entry = &self.palette[*i as usize];
+            let (r, g, b) = (
+                color[0] - entry[0],
+                color[1] - entry[1],
+                color[2] - entry[2],
+            );
+            let err = r * r + g * g + b * b;
+            if err < min {
+                min = err;
+                out = *i;
+            }
+        }
+        out
+    }
+
+    pub fn quantize(&self, rgb: &[u32]) -> Vec<u8> {
+        rgb.iter().map(|rgb| self.nearest(*rgb)).collect()
+    }
+
+    /// Quantizes a `width` x `height` image with rows stored one after the other.
+    pub fn quantize_image(
+        &self,
+        width: usize,
+        height: usize,
+        rgb: &[u32],
+        dither: ImageDither,
+    ) -> Vec<u8> {
+        assert_eq!(rgb.len(), width * height);
+        let pack = |color: [i32; 3]| {
+            let [r, g, b] = color.map(|c| c.clamp(0, 255) as u32);
+            r | g << 8 | b << 16
+        };
+        match dither {
+            ImageDither::None => self.quantize(rgb),
+            ImageDither::FloydSteinberg => {
+                // error in 1/16 for the current and the next row, with a guard pixel on each side
+                let mut error = vec![[0i32; 3]; width + 2];
+                let mut next_error = vec![[0i32; 3]; width + 2];
+                let mut out = Vec::with_capacity(rgb.len());
+                for row in rgb.chunks(width) {
+                    for (x, rgb) in row.iter().enumerate() {
+                        let mut color = channels(*rgb);
+                        for (c, error) in color.iter_mut().zip(error[x + 1].iter()) {
+                            *c = (*c + error / 16).clamp(0, 255);
+                        }
+                        let index = self.nearest(pack(color));
+                        out.push(index);
+                        let entry = &self.palette[index as usize];
+                        for c in 0..3 {
+                            let e = color[c] - entry[c];
+                            error[x + 2][c] += e * 7;
+                            next_error[x][c] += e * 3;
+                            next_error[x + 1][c] += e * 5;
+                            next_error[x + 2][c] += e;
+                        }
+                    }
+                    std::mem::swap(&mut error, &mut next_error);
+                    next_error.fill([0; 3]);
+                }
+                out
+            }
+            ImageDither::Ordered { kernel, spread } => {
+                let dither = Dither::new(kernel);
+                (0..height)
+                    .flat_map(|y| (0..width).map(move |x| (x, y)))
+                    .map(|(x, y)| {
+                        let threshold = dither.offset(x as i32, y as i32).0 - 0.5;
+                        let offset = (threshold * spread as f32).round() as i32;
+                        self.nearest(pack(channels(rgb[x + y * width]).map(|c| c + offset)))
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Builds a palette for a set of truecolor images by median cut: the colors of all images start
+/// in one box, which is split at the median of its widest channel until there is a box per free
+/// palette entry. Each box becomes the average of its colors.
+#[derive(Debug, Clone, Default)]
+pub struct PaletteBuilder {
+    histogram: HashMap<u32, u32>,
+    reserved: Vec<(u8, u32)>,
+}
+
+impl PaletteBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_image(&mut self, rgb: &[u32]) -> &mut Self {
+        for color in rgb {
+            *self.histogram.entry(color & 0xffffff).or_default() += 1;
+        }
+        self
+    }
+
+    /// Keeps palette entry `index` at `color`, e.g. for fullbrights or a transparent index.
+    pub fn reserve(&mut self, index: u8, color: u32) -> &mut Self {
+        self.reserved.retain(|(i, _)| *i != index);
+        self.reserved.push((index, color));
+        self
+    }
+
+    /// The palette, with the median cut colors in the entries that are not reserved. Entries
+    /// left over when the images have fewer colors are black.
+    pub fn build(&self) -> [u32; NUM_COLORS] {
+        let mut palette = [0u32; NUM_COLORS];
+        let mut free = vec![true; NUM_COLORS];
+        for (index, color) in self.reserved.iter() {
+            palette[*index as usize] = *color;
+            free[*index as usize] = false;
+        }
+        let free = (0..NUM_COLORS).filter(|i| free[*i]).collect::<Vec<_>>();
+
+        let mut colors = self
+            .histogram
+            .iter()
+            .map(|(color, count)| (channels(*color), *count))
+            .collect::<Vec<_>>();
+        // deterministic regardless of the hash map order
+        colors.sort_unstable();
+        let mut boxes = vec![colors];
+        while boxes.len() < free.len() {
+            // the box with the widest channel range, if it has more than one color
+            let widest = boxes
+                .iter()
+                .enumerate()
+                .map(|(i, colors)| {
+                    let (channel, range) = (0..3)
+                        .map(|c| {
+                            let (min, max) =
+                                colors.iter().fold((255, 0), |(min, max), (color, _)| {
+                                    (color[c].min(min), color[c].max(max))
+                                });
+                            (c, max - min)
+                        })
+                        .max_by_key(|(_, range)| *range)
+                        .unwrap();
+                    (i, channel, range)
+                })
+                .filter(|(_, _, range)| *range > 0)
+                .max_by_key(|(_, _, range)| *range);
+            let (i, channel, _) = match widest {
+                Some(widest) => widest,
+                None => break,
+            };
+            let mut colors = boxes.swap_remove(i);
+            colors.sort_unstable_by_key(|(color, _)| color[channel]);
+            let total = colors.iter().map(|(_, count)| *count as u64).sum::<u64>();
+            let mut sum = 0;
+            let median = colors
+                .iter()
+                .position(|(_, count)| {
+                    sum += *count as u64;
+                    2 * sum >= total
+                })
+                .unwrap();
+            // both halves get at least one color
+            let split = (median + 1).min(colors.len() - 1);
+            let upper = colors.split_off(split);
+            boxes.push(colors);
+            boxes.push(upper);
+        }
+
+        for (index, colors) in free.iter().zip(boxes.iter()) {
+            let total = colors.iter().map(|(_, count)| *count as u64).sum::<u64>();
+            if total == 0 {
+                continue;
+            }
+            palette[*index] = (0..3).fold(0, |packed, c| {
+                let sum = colors
+                    .iter()
+                    .map(|(color, count)| color[c] as u64 * *count as u64)
+                    .sum::<u64>();
+                packed | (((sum + total / 2) / total) as u32) << (8 * c)
+            });
+        }
+        palette
+    }
+}
+
+#[test]
+fn test_matches_linear_search() {
+    let mut seed = 12345u32;
+    let mut random = move || {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        seed >> 8
+    };
+    let mut palette = [0u32; NUM_COLORS];
+    for color in palette.iter_mut() {
+        *color = random() & 0xffffff;
+    }
+    // duplicates and clusters, where ties have to resolve like in the linear search
+    palette[200] = palette[10];
+    palette[201] = palette[10] ^ 0x010000;
+    for i in 0..16 {
+        palette[220 + i] = 0x404040 + i as u32;
+    }
+
+    let mut colors = (0..50000).map(|_| random() & 0xffffff).collect::<Vec<_>>();
+    colors.extend(palette.iter());
+    colors.extend((0..=255).map(|c| c * 0x010101));
+    colors.extend([0x3f3f3f, 0x404040, 0x41413f]);
+
+    assert_eq!(
+        Quantizer::new(&palette).quantize(&colors),
+        crate::palette::quantize(&palette, &colors)
+    );
+    let exclude = [10, 220, 221, 255];
+    assert_eq!(
+        Quantizer::excluding(&palette, &exclude).quantize(&colors),
+        crate::palette::quantize_excluding(&palette, &colors, &exclude)
+    );
+}
+
+#[test]
+fn test_quantize_image() {
+    // 8 gray levels, 36 apart
+    let mut palette = [0u32; NUM_COLORS];
+    for (i, color) in palette.iter_mut().enumerate() {
+        *color = (i as u32 % 8 * 255 / 7) * 0x010101;
+    }
+    let quantizer = Quantizer::new(&palette);
+    // horizontal gradient
+    let (width, height) = (256, 16);
+    let rgb = (0..width * height)
+        .map(|i| (i % width) as u32 * 0x010101)
+        .collect::<Vec<_>>();
+    // mean error of 8x8 block averages, i.e. how far the image is off when viewed from afar
+    let block_error = |indices: &[u8]| {
+        let mut error = 0.0;
+        for by in 0..height / 8 {
+            for bx in 0..width / 8 {
+                let mut diff = 0.0;
+                for y in by * 8..by * 8 + 8 {
+                    for x in bx * 8..bx * 8 + 8 {
+                        let i = x + y * width;
+                        diff +=
+                            (palette[indices[i] as usize] & 0xff) as f32 - (rgb[i] & 0xff) as f32;
+                    }
+                }
+                error += (diff / 64.0).abs();
+            }
+        }
+        error / (width * height / 64) as f32
+    };
+
+    let nearest = quantizer.quantize_image(width, height, &rgb, ImageDither::None);
+    assert_eq!(nearest, crate::palette::quantize(&palette, &rgb));
+    let floyd_steinberg =
+        quantizer.quantize_image(width, height, &rgb, ImageDither::FloydSteinberg);
+    let ordered = quantizer.quantize_image(
+        width,
+        height,
+        &rgb,
+        ImageDither::Ordered {
+            kernel: DitherKernel::Bayer4x4,
+            spread: 36,
+        },
+    );
+    let nearest_error = block_error(&nearest);
+    assert!(nearest_error > 5.0);
+    assert!(block_error(&floyd_steinberg) < nearest_error / 4.0);
+    assert!(block_error(&ordered) < nearest_error / 4.0);
+    // colors of the palette come out unchanged
+    let flat = vec![palette[3]; width * height];
+    assert!(quantizer
+        .quantize_image(width, height, &flat, ImageDither::FloydSteinberg)
+        .iter()
+        .all(|i| *i == 3));
+}
+
+#[test]
+fn test_palette_builder() {
+    // few colors come out exactly, around the reserved entries
+    let colors = [0x102030, 0x405060, 0xff0000, 0x00ff00];
+    let mut builder = PaletteBuilder::new();
+    builder
+        .add_image(&colors)
+        .add_image(&colors[..2])
+        .reserve(0, 0xff00ff)
+        .reserve(255, 0xffffff);
+    let palette = builder.build();
+    assert_eq!(palette[0], 0xff00ff);
+    assert_eq!(palette[255], 0xffffff);
+    let mut built = palette[1..5].to_vec();
+    built.sort_unstable();
+    let mut expected = colors.to_vec();
+    expected.sort_unstable();
+    assert_eq!(built, expected);
+    assert!(palette[5..255].iter().all(|c| *c == 0));
+
+    // a smooth image is matched closely
+    let (width, height) = (64, 64);
+    let rgb = (0..width * height)
+        .map(|i| {
+            let (x, y) = ((i % width) as u32, (i / width) as u32);
+            (x * 4) | (y * 4) << 8 | ((x + y) * 2) << 16
+        })
+        .collect::<Vec<_>>();
+    let palette = PaletteBuilder::new().add_image(&rgb).build();
+    let quantized = Quantizer::new(&palette).quantize(&rgb);
+    let max_error = rgb
+        .iter()
+        .zip(quantized.iter())
+        .map(|(color, index)| {
+            let (a, b) = (channels(*color), channels(palette[*index as usize]));
+            (0..3).map(|c| (a[c] - b[c]).abs()).max().unwrap()
+        })
+        .max()
+        .unwrap();
+    assert!(max_error <= 16, "{}", max_error);
+}
@@ -1,4 +1,7 @@
-use std::{fmt::Debug, ops::AddAssign};
+use std::{
+    fmt::{Debug, Display},
+    ops::AddAssign,
+};
 
 use num_traits::One;
 
@@ -83,17 +86,147 @@ pub fn rasterize_triangle<T, P, G, S, M, D>(
     }
 }
 
-pub static G_COUNT: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+/// Counters collected during a render. Every render (or rendering thread) owns its own instance,
+/// partial results are merged with `+=`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RasterStats {
+    /// polygons handed to a rasterizer
+    pub polygons: u64,
+    /// polygons culled entirely by clipping before they reached a rasterizer
+    pub clipped: u64,
+    /// polygons without any scanlines or area
+    pub degenerate: u64,
+    /// polygon edges set up by the scanline rasterizer
+    pub edges: u64,
+    pub scanlines: u64,
+    /// fragments produced by the rasterizers, before depth testing
+    pub fragments: u64,
+    pub depth_rejected: u64,
+    /// fragments written to a pixel that had already been written during this render
+    pub overdraw: u64,
+}
+
+impl AddAssign for RasterStats {
+    fn add_assign(&mut self, other: Self) {
+        self.polygons += other.polygons;
+        self.clipped += other.clipped;
+        self.degenerate += other.degenerate;
+        self.edges += other.edges;
+        self.scanlines += other.scanlines;
+        self.fragments += other.fragments;
+        self.depth_rejected += other.depth_rejected;
+        self.overdraw += other.overdraw;
+    }
+}
+
+impl Display for RasterStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "polygons: {} clipped: {} degenerate: {} edges: {} scanlines: {} fragments: {} depth rejected: {} overdraw: {}",
+            self.polygons,
+            self.clipped,
+            self.degenerate,
+            self.edges,
+            self.scanlines,
+            self.fragments,
+            self.depth_rejected,
+            self.overdraw
+        )
+    }
+}
+
+/// Decides which pixels a polygon covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FillRule {
+    /// Vertex and span coordinates are truncated to integers and slopes start at the vertex value.
+    /// Cheap, but polygons sharing an edge can leave cracks or draw pixels twice.
+    Truncate,
+    /// Pixels are sampled at their centers. A pixel whose center lies exactly on an edge belongs
+    /// to the polygon only if that is a top or left edge, and all slopes are prestepped to the
+    /// first covered pixel center. Polygons sharing an edge touch every pixel exactly once.
+    TopLeft,
+}
+
+impl FillRule {
+    /// First pixel row (or column) at or after coordinate `v`.
+    #[inline(always)]
+    pub fn to_pixel(self, v: f32) -> i32 {
+        match self {
+            FillRule::Truncate => v as i32,
+            FillRule::TopLeft => (v - 0.5).ceil() as i32,
+        }
+    }
+
+    /// Number of steps from coordinate `v` to `pixel`. With `Truncate` this counts whole pixels
+    /// from `to_pixel(v)`, with `TopLeft` it is the distance to the pixel center.
+    #[inline(always)]
+    pub fn prestep(self, v: f32, pixel: i32) -> f32 {
+        match self {
+            FillRule::Truncate => (pixel - v as i32) as f32,
+            FillRule::TopLeft => pixel as f32 + 0.5 - v,
+        }
+    }
+
+    /// Coordinate at which attributes of `pixel` are sampled.
+    #[inline(always)]
+    pub fn sample_point(self, pixel: i32) -> f32 {
+        match self {
+            FillRule::Truncate => pixel as f32,
+            FillRule::TopLeft => pixel as f32 + 0.5,
+        }
+    }
+}
+
+/// Screen rectangle that rasterization is restricted to, covering pixels `x0..x1` and `y0..y1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scissor {
+    pub x0: i32,
+    pub y0: i32,
+    pub x1: i32,
+    pub y1: i32,
+}
+
+impl Scissor {
+    pub fn new(x0: i32, y0: i32, x1: i32, y1: i32) -> Self {
+        Scissor { x0, y0, x1, y1 }
+    }
+    /// Whole `width` x `height` viewport.
+    pub fn from_size(width: u32, height: u32) -> Self {
+        Scissor::new(0, 0, width as i32, height as i32)
+    }
+    pub fn intersect(&self, other: &Scissor) -> Self {
+        Scissor::new(
+            self.x0.max(other.x0),
+            self.y0.max(other.y0),
+            self.x1.min(other.x1),
+            self.y1.min(other.y1),
+        )
+    }
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x0 && x < self.x1 && y >= self.y0 && y < self.y1
+    }
+}
 
+/// Scanline rasterizer for convex polygons.
+///
+/// `make_slope` is called with the edge end points, the number of scanlines between them and the
+/// prestep from the first end point to the first scanline's sample point, which the slope must
+/// apply itself. Scanlines outside of `scissor` are skipped by prestepping further, clipping spans
+/// in x is up to `draw_scanline`. The last argument of `draw_scanline` identifies the edge pair
+/// within the render.
 pub fn rasterize_polygon<P, G, S, M, D>(
     points: &[P],
+    fill_rule: FillRule,
+    scissor: &Scissor,
+    stats: &mut RasterStats,
     get_xy: G,
     make_slope: M,
     mut draw_scanline: D,
 ) where
     G: Fn(&P) -> (f32, f32),
     S: Debug + Default,
-    M: Fn(&P, &P, f32) -> S,
+    M: Fn(&P, &P, f32, f32) -> S,
     D: FnMut(i32, &mut S, &mut S, u32),
 {
     let compare = |elem: &P, prev: &P| {
@@ -115,15 +248,18 @@ pub fn rasterize_polygon<P, G, S, M, D>(
         }
     }
 
+    stats.polygons += 1;
     if first_point == last_point {
+        stats.degenerate += 1;
         return;
     }
+    let mut num_rows = 0;
 
     let mut cur_point_left = first_point;
     let mut cur_point_right = first_point;
     let mut right_side = false;
 
-    let mut cur_y = get_xy(&points[first_point]).1 as i32;
+    let mut cur_y = fill_rule.to_pixel(get_xy(&points[first_point]).1);
     let mut next_y_left = cur_y;
     let mut next_y_right = cur_y;
 
@@ -155,12 +291,20 @@ pub fn rasterize_polygon<P, G, S, M, D>(
                 *cur_point = points.len() - 1;
             }
         }
-        *next_y = get_xy(&points[*cur_point]).1 as i32;
-        *slope = make_slope(
-            &points[prev_point],
-            &points[*cur_point],
-            (*next_y - cur_y) as f32,
-        );
+        *next_y = fill_rule.to_pixel(get_xy(&points[*cur_point]).1);
+        // the slope is not used before the first scanline inside the scissor rectangle
+        let start_y = cur_y.max(scissor.y0);
+        let (num_steps, prestep) = match fill_rule {
+            FillRule::Truncate => ((*next_y - cur_y) as f32, (start_y - cur_y) as f32),
+            FillRule::TopLeft => {
+                let prev_y = get_xy(&points[prev_point]).1;
+                (
+                    get_xy(&points[*cur_point]).1 - prev_y,
+                    fill_rule.prestep(prev_y, start_y),
+                )
+            }
+        };
+        *slope = make_slope(&points[prev_point], &points[*cur_point], num_steps, prestep);
         right_side = next_y_left > next_y_right;
 
         let limit = if !right_side {
@@ -168,10 +312,24 @@ pub fn rasterize_polygon<P, G, S, M, D>(
         } else {
             next_y_right
         };
-        let aux = G_COUNT.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-        while cur_y < limit {
-            draw_scanline(cur_y, &mut slope_left, &mut slope_right, aux);
-            cur_y += 1;
+        let aux = stats.edges as u32;
+        stats.edges += 1;
+        num_rows += (limit - cur_y).max(0);
+
+        let mut y = cur_y.max(scissor.y0);
+        let end_y = limit.min(scissor.y1);
+        stats.scanlines += (end_y - y).max(0) as u64;
+        while y < end_y {
+            draw_scanline(y, &mut slope_left, &mut slope_right, aux);
+            y += 1;
         }
+        cur_y = cur_y.max(limit);
+        if cur_y >= scissor.y1 {
+            // the rest is below the scissor rectangle, that doesn't make the polygon degenerate
+            return;
+        }
+    }
+    if num_rows == 0 {
+        stats.degenerate += 1;
     }
 }
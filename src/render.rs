@@ -0,0 +1,241 @@
+use std::ops::Range;
+
+use crate::{
+    palette::Framebuffer,
+    rasterize::{FillRule, RasterStats, Scissor},
+    span::SpanShader,
+    texpoly::{self, Point, Varyings},
+};
+
+/// Clipped and projected polygon together with the material it is drawn with.
+#[derive(Debug, Clone)]
+pub struct Polygon<V = Point> {
+    pub points: Vec<V>,
+    pub material: usize,
+}
+
+impl<V> Polygon<V> {
+    // scanlines the polygon can touch
+    fn rows<const N: usize>(&self, fill_rule: FillRule) -> Range<i32>
+    where
+        V: Varyings<N>,
+    {
+        let (ymin, ymax) = self
+            .points
+            .iter()
+            .map(|p| p.position().1)
+            .fold((f32::MAX, f32::MIN), |(min, max), y| {
+                (min.min(y), max.max(y))
+            });
+        fill_rule.to_pixel(ymin)..fill_rule.to_pixel(ymax) + 1
+    }
+}
+
+// horizontal slice of the framebuffer owned by one thread
+struct Band<'a> {
+    rows: Range<i32>,
+    width: usize,
+    framebuffer: &'a mut [u8],
+    zbuffer: &'a mut [f32],
+}
+
+impl<'a> Band<'a> {
+    fn draw<V, const N: usize>(
+        &mut self,
+        polygons: &[&Polygon<V>],
+        fill_rule: FillRule,
+        scissor: &Scissor,
+        shaders: &[&(dyn SpanShader<N> + Sync)],
+    ) -> RasterStats
+    where
+        V: Varyings<N>,
+    {
+        let mut stats = RasterStats::default();
+        for polygon in polygons {
+            let mut polygon_stats = RasterStats::default();
+            let shader = shaders[polygon.material];
+            let rows = self.rows.clone();
+            texpoly::draw_polygon_spans(
+                &polygon.points,
+                fill_rule,
+                scissor,
+                rows,
+                &mut polygon_stats,
+                |span| {
+                    let row_start = (span.y - self.rows.start) as usize * self.width;
+                    let row = row_start..row_start + self.width;
+                    shader.draw_span(
+                        span,
+                        &mut self.framebuffer[row.clone()],
+                        &mut self.zbuffer[row],
+                        &mut stats,
+                    );
+                },
+            );
+            stats.fragments += polygon_stats.fragments;
+
+            // every band walks all edges of its polygons. Only the band holding the first
+            // scanline counts them, so the totals don't depend on the number of bands.
+            let first_row = polygon.rows::<N>(fill_rule).start.max(scissor.y0);
+            if self.rows.contains(&first_row) {
+                polygon_stats.fragments = 0;
+                stats += polygon_stats;
+            }
+        }
+        stats
+    }
+}
+
+/// Renders `polygons` in submission order, clipped to `scissor`. Every polygon is drawn by
+/// `shaders[polygon.material]`, which is responsible for depth testing.
+pub fn draw_polygons<V, const N: usize>(
+    fb: &mut Framebuffer,
+    polygons: &[Polygon<V>],
+    fill_rule: FillRule,
+    scissor: &Scissor,
+    stats: &mut RasterStats,
+    shaders: &[&(dyn SpanShader<N> + Sync)],
+) where
+    V: Varyings<N> + Sync,
+{
+    draw_polygons_banded(fb, polygons, fill_rule, scissor, 1, stats, shaders)
+}
+
+/// Same as `draw_polygons`, but splits the framebuffer into `num_bands` horizontal bands which
+/// are rasterized in parallel. Every band draws the polygons overlapping it in submission order,
+/// so the result (including `stats`) is identical to the single threaded one.
+pub fn draw_polygons_banded<V, const N: usize>(
+    fb: &mut Framebuffer,
+    polygons: &[Polygon<V>],
+    fill_rule: FillRule,
+    scissor: &Scissor,
+    num_bands: usize,
+    stats: &mut RasterStats,
+    shaders: &[&(dyn SpanShader<N> + Sync)],
+) where
+    V: Varyings<N> + Sync,
+{
+    let width = fb.width() as usize;
+    let height = fb.height() as usize;
+    let scissor = scissor.intersect(&Scissor::from_size(fb.width(), fb.height()));
+    let num_bands = num_bands.max(1);
+    let band_height = height.div_ceil(num_bands).max(1);
+
+    let mut bands = fb
+        .framebuffer
+        .chunks_mut(band_height * width)
+        .zip(fb.zbuffer.chunks_mut(band_height * width))
+        .enumerate()
+        .map(|(i, (framebuffer, zbuffer))| {
+            let start = (i * band_height) as i32;
+            Band {
+                rows: start..start + (framebuffer.len() / width) as i32,
+                width,
+                framebuffer,
+                zbuffer,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut bins = vec![Vec::new(); bands.len()];
+    for polygon in polygons {
+        let rows = polygon.rows::<N>(fill_rule);
+        let rows = rows.start.max(scissor.y0)..rows.end.min(scissor.y1);
+        for (band, bin) in bands.iter().zip(bins.iter_mut()) {
+            if rows.start < band.rows.end && band.rows.start < rows.end {
+                bin.push(polygon);
+            }
+        }
+    }
+
+    if bands.len() == 1 {
+        *stats += bands[0].draw(&bins[0], fill_rule, &scissor, shaders);
+        return;
+    }
+    let scissor = &scissor;
+    std::thread::scope(|scope| {
+        let threads = bands
+            .iter_mut()
+            .zip(bins.iter())
+            .map(|(band, bin)| scope.spawn(move || band.draw(bin, fill_rule, scissor, shaders)))
+            .collect::<Vec<_>>();
+        for thread in threads {
+            *stats += thread.join().unwrap();
+        }
+    });
+}
+
+/// Number of bands to use for `draw_polygons_banded` on this machine.
+pub fn default_num_bands() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+#[test]
+fn test_banded_matches_single_threaded() {
+    let palette = [0u32; crate::palette::NUM_COLORS];
+    let polygons = [
+        Polygon {
+            points: vec![
+                (3.2, 2.7, 20.0, 0.0, 0.0),
+                (90.1, 20.3, 40.0, 64.0, 0.0),
+                (70.4, 61.9, 80.0, 64.0, 64.0),
+                (5.5, 50.0, 30.0, 0.0, 64.0),
+            ],
+            material: 0,
+        },
+        Polygon {
+            points: vec![
+                (10.0, 10.0, 25.0, 0.0, 0.0),
+                (60.5, 15.0, 25.0, 32.0, 0.0),
+                (30.0, 63.5, 50.0, 0.0, 32.0),
+            ],
+            material: 1,
+        },
+    ];
+    let shader = |span: &crate::span::Span,
+                  pixels: &mut [u8],
+                  zbuffer: &mut [f32],
+                  stats: &mut RasterStats| {
+        span.for_each(|x, z, u, v| {
+            if !crate::span::depth_test(zbuffer, x, z, stats) {
+                return;
+            }
+            pixels[x as usize] = (x as f32 * 0.25 + span.y as f32 * 0.5 + z + u * 3.0 + v * 7.0)
+                as u8
+                ^ span.aux as u8;
+        });
+    };
+    let flat = crate::span::FlatShader { color: 7 };
+    let shaders: [&(dyn SpanShader + Sync); 2] = [&shader, &flat];
+
+    for fill_rule in [FillRule::Truncate, FillRule::TopLeft] {
+        let mut reference = Framebuffer::new(100, 64, &palette);
+        let mut reference_stats = RasterStats::default();
+        let scissor = Scissor::from_size(100, 64);
+        draw_polygons(
+            &mut reference,
+            &polygons,
+            fill_rule,
+            &scissor,
+            &mut reference_stats,
+            &shaders,
+        );
+        assert!(reference_stats.depth_rejected > 0 && reference_stats.overdraw > 0);
+        for num_bands in [2, 3, 7, 64, 100] {
+            let mut fb = Framebuffer::new(100, 64, &palette);
+            let mut stats = RasterStats::default();
+            draw_polygons_banded(
+                &mut fb, &polygons, fill_rule, &scissor, num_bands, &mut stats, &shaders,
+            );
+            assert_eq!(stats, reference_stats);
+            assert_eq!(fb.framebuffer, reference.framebuffer);
+            assert!(fb
+                .zbuffer
+                .iter()
+                .zip(reference.zbuffer.iter())
+                .all(|(a, b)| a.to_bits() == b.to_bits()));
+        }
+    }
+}
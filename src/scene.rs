@@ -0,0 +1,243 @@
+use glam::{IVec3, Mat3};
+
+use crate::{
+    clip_polygon_inplace, level,
+    math::{self, prelude::*},
+    palette::{Framebuffer, LightModel, NUM_COLORS},
+    rasterize::{FillRule, RasterStats, Scissor},
+    render::{self, Polygon},
+    span::{SpanShader, TexturedShader},
+    texpoly::Vertex,
+    texture::Mipmaps,
+};
+
+/// Viewer position, looking down +z for a `yaw` of 0. `fov` is the horizontal field of view in
+/// degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    pub pos: Vec3,
+    pub yaw: f32,
+    pub fov: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            // in the example level, down the corridor with the grate
+            pos: Vec3::new(160.0, 0.0, 20.0),
+            yaw: 0.0,
+            fov: 100.0,
+        }
+    }
+}
+
+impl Camera {
+    /// Unit vector in view direction, in world space.
+    pub fn forward(&self) -> Vec3 {
+        Mat3::from_rotation_y(-self.yaw) * Vec3::Z
+    }
+
+    /// Unit vector pointing to the right of the screen, in world space.
+    pub fn right(&self) -> Vec3 {
+        Mat3::from_rotation_y(-self.yaw) * Vec3::X
+    }
+
+    /// World space point `p` in view space, where +z is forward and +x is right.
+    pub fn to_view(&self, p: Vec3) -> Vec3 {
+        Mat3::from_rotation_y(self.yaw) * (p - self.pos)
+    }
+}
+
+/// Level geometry with per vertex light, ready to be rendered without a window.
+pub struct Scene {
+    pub points: Vec<Vec3>,
+    pub quads: Vec<level::Quad>,
+    pub vertex_lights: Vec<f32>,
+    /// Indexed by the material of the quads.
+    pub textures: Vec<Mipmaps>,
+}
+
+impl Scene {
+    pub fn new(
+        blockmap: &level::Blockmap,
+        ambient: f32,
+        lights: &[level::PointLight],
+        textures: Vec<Mipmaps>,
+    ) -> Self {
+        let (points, quads) = blockmap.get_polygons();
+        let vertex_lights = level::vertex_lights(&points, ambient, lights);
+        Scene {
+            points,
+            quads,
+            vertex_lights,
+            textures,
+        }
+    }
+
+    /// The walkthrough level of `texture_indexed`.
+    pub fn example(palette: &[u32; NUM_COLORS]) -> Self {
+        let mut blockmap = level::Blockmap::new();
+        blockmap.add(IVec3::ZERO, &level::EXAMPLE_FLOOR);
+        let textures = level::MATERIALS
+            .iter()
+            .map(|material| Mipmaps::from_indexed(material.load(), palette))
+            .collect();
+        Self::new(&blockmap, 0.25, &level::example_lights(), textures)
+    }
+
+    /// Clears `fb` and draws the scene as seen from `camera`, lit through `colormap`.
+    pub fn render(
+        &self,
+        fb: &mut Framebuffer,
+        camera: &Camera,
+        fill_rule: FillRule,
+        colormap: &[[u8; NUM_COLORS]],
+        light_model: LightModel,
+        stats: &mut RasterStats,
+    ) {
+        let width = fb.width() as f32;
+        let height = fb.height() as f32;
+        let (project, unproject) = math::perspective(width, height, camera.fov);
+        let frustum = crate::make_frustum(
+            &[
+                Vec2::ZERO,
+                Vec2::new(width - 1.0, 0.0),
+                Vec2::new(width - 1.0, height - 1.0),
+                Vec2::new(0.0, height - 1.0),
+            ],
+            &unproject,
+        );
+        // the frustum looks down -z, so the view is turned around
+        let turn = Mat3::from_rotation_y(std::f32::consts::PI);
+
+        let mut polygons = Vec::new();
+        for (p0, p1, p2, p3, material) in self.quads.iter().cloned() {
+            let texture = self.textures[material].base();
+            let mut poly = [p0, p1, p2, p3]
+                .iter()
+                .map(|(i, u, v)| {
+                    (
+                        turn * camera.to_view(self.points[*i as usize]),
+                        Vec3::new(
+                            *u * texture.width() as f32,
+                            *v * texture.height() as f32,
+                            self.vertex_lights[*i as usize],
+                        ),
+                    )
+                })
+                .collect::<Vec<_>>();
+            for plane in frustum.iter() {
+                clip_polygon_inplace(*plane, &mut poly);
+            }
+            if poly.len() < 3 {
+                stats.clipped += 1;
+                continue;
+            }
+            let points = poly
+                .iter()
+                .map(|(p, t)| {
+                    let v = project(*p);
+                    Vertex {
+                        x: v.x,
+                        y: v.y,
+                        z: p.z,
+                        attributes: t.to_array(),
+                    }
+                })
+                .collect();
+            polygons.push(Polygon { points, material });
+        }
+
+        let shaders = self
+            .textures
+            .iter()
+            .map(|texture| TexturedShader {
+                light: light_model,
+                ..TexturedShader::new(texture, colormap)
+            })
+            .collect::<Vec<_>>();
+        let shaders = shaders
+            .iter()
+            .map(|shader| shader as &(dyn SpanShader<3> + Sync))
+            .collect::<Vec<_>>();
+        fb.clear();
+        render::draw_polygons(
+            fb,
+            &polygons,
+            fill_rule,
+            &Scissor::from_size(fb.width(), fb.height()),
+            stats,
+            &shaders,
+        );
+    }
+}
+
+#[test]
+fn test_camera_axes() {
+    for yaw in [0.0f32, 0.5, 1.0, 2.0, 3.0, -2.5] {
+        let camera = Camera {
+            pos: Vec3::new(3.0, 1.0, -7.0),
+            yaw,
+            ..Default::default()
+        };
+        let forward = camera.to_view(camera.pos + camera.forward());
+        let right = camera.to_view(camera.pos + camera.right());
+        assert!(forward.abs_diff_eq(Vec3::Z, 1e-6), "{} {:?}", yaw, forward);
+        assert!(right.abs_diff_eq(Vec3::X, 1e-6), "{} {:?}", yaw, right);
+    }
+}
+
+#[test]
+fn test_render_golden() {
+    let (palette, colormap) = crate::palette::read_colormap();
+    let scene = Scene::example(&palette);
+    let camera = Camera {
+        // down the corridor with the grate
+        pos: Vec3::new(160.0, 0.0, 20.0),
+        yaw: 0.0,
+        ..Default::default()
+    };
+    let render = |fill_rule| {
+        let mut fb = Framebuffer::new(160, 120, &palette);
+        let mut stats = RasterStats::default();
+        scene.render(
+            &mut fb,
+            &camera,
+            fill_rule,
+            &colormap,
+            LightModel::default(),
+            &mut stats,
+        );
+        assert!(stats.fragments > 0);
+        let mut headless = crate::present::Headless::new();
+        fb.present(&mut headless).unwrap();
+        headless.rgb
+    };
+    let top_left = render(FillRule::TopLeft);
+    crate::image::assert_golden("assets/golden/example_top_left.ppm", 160, 120, &top_left, 0);
+    // a change of the fill rule shows up in the diff
+    let truncate = render(FillRule::Truncate);
+    let diff = crate::image::compare(160, 120, &top_left, &truncate, 0);
+    assert!(!diff.is_match());
+}
+
+#[test]
+fn test_default_camera() {
+    let (palette, colormap) = crate::palette::read_colormap();
+    let scene = Scene::example(&palette);
+    let mut fb = Framebuffer::new(320, 240, &palette);
+    let mut stats = RasterStats::default();
+    scene.render(
+        &mut fb,
+        &Camera::default(),
+        FillRule::TopLeft,
+        &colormap,
+        LightModel::default(),
+        &mut stats,
+    );
+    // something of the level is in view, and the frame is not all black
+    assert!(stats.fragments > 0);
+    let mut headless = crate::present::Headless::new();
+    fb.present(&mut headless).unwrap();
+    assert!(headless.rgb.iter().any(|color| *color != 0));
+}
@@ -14,6 +14,15 @@ impl SlopeData {
             step: (end - begin) as f32 * inv_step,
         }
     }
+    #[inline(always)]
+    pub fn step(&self) -> f32 {
+        self.step
+    }
+    /// Advance by a fractional number of steps, e.g. from a vertex to the first pixel center.
+    #[inline(always)]
+    pub fn prestep(&mut self, amount: f32) {
+        self.begin += self.step * amount;
+    }
 }
 impl Slope<f32> for SlopeData {
     #[inline(always)]
@@ -40,6 +49,10 @@ impl Slope4x {
             step: (end - begin) * inv_step,
         }
     }
+    #[inline(always)]
+    pub fn prestep(&mut self, amount: f32) {
+        self.begin += self.step * amount;
+    }
 }
 impl Slope<glam::Vec4> for Slope4x {
     #[inline(always)]
@@ -51,3 +64,68 @@ impl Slope<glam::Vec4> for Slope4x {
         self.begin += self.step;
     }
 }
+
+// apply f to a and b four lanes at a time, storing the result in a. Lanes past N are zero.
+#[inline(always)]
+fn zip4<const N: usize, F>(a: &mut [f32; N], b: &[f32; N], f: F)
+where
+    F: Fn(glam::Vec4, glam::Vec4) -> glam::Vec4,
+{
+    let mut a_chunks = a.chunks_exact_mut(4);
+    let mut b_chunks = b.chunks_exact(4);
+    for (a, b) in (&mut a_chunks).zip(&mut b_chunks) {
+        f(glam::Vec4::from_slice(a), glam::Vec4::from_slice(b)).write_to_slice(a);
+    }
+    let (a, b) = (a_chunks.into_remainder(), b_chunks.remainder());
+    if !a.is_empty() {
+        let mut a4 = [0.0; 4];
+        let mut b4 = [0.0; 4];
+        a4[..a.len()].copy_from_slice(a);
+        b4[..b.len()].copy_from_slice(b);
+        let result = f(glam::Vec4::from(a4), glam::Vec4::from(b4)).to_array();
+        a.copy_from_slice(&result[..a.len()]);
+    }
+}
+
+/// Like `Slope4x` for any number of values, stepped four at a time.
+#[derive(Debug, Clone, Copy)]
+pub struct SlopeNx<const N: usize> {
+    pub begin: [f32; N],
+    pub step: [f32; N],
+}
+
+impl<const N: usize> Default for SlopeNx<N> {
+    fn default() -> Self {
+        SlopeNx {
+            begin: [0.0; N],
+            step: [0.0; N],
+        }
+    }
+}
+
+impl<const N: usize> SlopeNx<N> {
+    #[inline(always)]
+    pub fn new(begin: [f32; N], end: [f32; N], num_steps: f32) -> Self {
+        let inv_step = glam::Vec4::splat(1.0 / num_steps);
+        let mut step = end;
+        zip4(&mut step, &begin, |end, begin| (end - begin) * inv_step);
+        SlopeNx { begin, step }
+    }
+    #[inline(always)]
+    pub fn prestep(&mut self, amount: f32) {
+        let amount = glam::Vec4::splat(amount);
+        zip4(&mut self.begin, &self.step, |begin, step| {
+            begin + step * amount
+        });
+    }
+}
+impl<const N: usize> Slope<[f32; N]> for SlopeNx<N> {
+    #[inline(always)]
+    fn get(&self) -> [f32; N] {
+        self.begin
+    }
+    #[inline(always)]
+    fn advance(&mut self) {
+        zip4(&mut self.begin, &self.step, |begin, step| begin + step);
+    }
+}
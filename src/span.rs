@@ -0,0 +1,700 @@
+use crate::{
+    palette::{Framebuffer, LightModel, NUM_COLORS},
+    rasterize::{FillRule, RasterStats, Scissor},
+    texpoly::{self, Varyings},
+    texture::{Dither, Filter, Mipmaps, Wrap},
+    tranmap::Tranmap,
+};
+
+/// Pixels `x0..x1` of scanline `y`. 1/z and the `N` attributes divided by z are given at the
+/// first pixel together with their change from one pixel to the next and from one scanline to the
+/// next.
+#[derive(Debug, Clone, Copy)]
+pub struct Span<const N: usize = 2> {
+    pub y: i32,
+    pub x0: i32,
+    pub x1: i32,
+    pub zi: f32,
+    pub zi_step: f32,
+    pub zi_dy: f32,
+    pub start: [f32; N],
+    pub step: [f32; N],
+    pub dy: [f32; N],
+    pub aux: u32,
+}
+
+impl<const N: usize> Span<N> {
+    /// z and the attributes at pixel `x`.
+    #[inline(always)]
+    pub fn eval(&self, x: i32) -> (f32, [f32; N]) {
+        let offset = (x - self.x0) as f32;
+        let z = 1.0 / (self.zi + self.zi_step * offset);
+        let mut attributes = self.start;
+        for (attribute, step) in attributes.iter_mut().zip(self.step.iter()) {
+            *attribute = (*attribute + step * offset) * z;
+        }
+        (z, attributes)
+    }
+
+    /// Level of detail for texture coordinates in attributes 0 and 1, given z, u and v of a pixel:
+    /// log2 of the larger distance in texels between neighbouring pixels along x and y.
+    #[inline(always)]
+    pub fn uv_lod(&self, z: f32, u: f32, v: f32) -> f32 {
+        let dudx = (self.step[0] - u * self.zi_step) * z;
+        let dvdx = (self.step[1] - v * self.zi_step) * z;
+        let dudy = (self.dy[0] - u * self.zi_dy) * z;
+        let dvdy = (self.dy[1] - v * self.zi_dy) * z;
+        let rho2 = (dudx * dudx + dvdx * dvdx).max(dudy * dudy + dvdy * dvdy);
+        0.5 * rho2.log2()
+    }
+
+    /// Calls `f` with `(x, z, attributes)` for every pixel of the span.
+    #[inline(always)]
+    pub fn for_each_varyings<F>(&self, mut f: F)
+    where
+        F: FnMut(i32, f32, [f32; N]),
+    {
+        let mut zi = self.zi;
+        let mut attributes = self.start;
+        for x in self.x0..self.x1 {
+            let z = 1.0 / zi;
+            f(x, z, attributes.map(|a| a * z));
+            zi += self.zi_step;
+            for (attribute, step) in attributes.iter_mut().zip(self.step.iter()) {
+                *attribute += step;
+            }
+        }
+    }
+
+    /// Like `for_each_varyings`, but with the perspective divide done according to
+    /// `perspective`.
+    #[inline(always)]
+    pub fn for_each_varyings_perspective<F>(&self, perspective: Perspective, mut f: F)
+    where
+        F: FnMut(i32, f32, [f32; N]),
+    {
+        let run_length = match perspective {
+            Perspective::Exact => return self.for_each_varyings(f),
+            Perspective::Subdivided(n) => n.max(1) as i32,
+            Perspective::Affine => i32::MAX,
+        };
+        let mut x = self.x0;
+        let mut begin = self.eval(x);
+        while x < self.x1 {
+            let len = run_length.min(self.x1 - x);
+            // the last run ends on the last pixel instead of sampling beyond the span
+            let (end, num_steps) = if x + len < self.x1 {
+                (self.eval(x + len), len)
+            } else {
+                (self.eval(self.x1 - 1), (len - 1).max(1))
+            };
+            let inv_steps = 1.0 / num_steps as f32;
+            let z_step = (end.0 - begin.0) * inv_steps;
+            let mut step = end.1;
+            for (step, begin) in step.iter_mut().zip(begin.1.iter()) {
+                *step = (*step - begin) * inv_steps;
+            }
+            let (mut z, mut attributes) = begin;
+            for x in x..x + len {
+                f(x, z, attributes);
+                z += z_step;
+                for (attribute, step) in attributes.iter_mut().zip(step.iter()) {
+                    *attribute += step;
+                }
+            }
+            begin = end;
+            x += len;
+        }
+    }
+
+    /// Largest difference of any attribute (in texels for u and v) between `perspective` and the
+    /// exact divide.
+    pub fn texel_error(&self, perspective: Perspective) -> f32 {
+        let mut exact = Vec::with_capacity((self.x1 - self.x0).max(0) as usize);
+        self.for_each_varyings(|_, _, attributes| exact.push(attributes));
+        let mut error = 0.0f32;
+        self.for_each_varyings_perspective(perspective, |x, _, attributes| {
+            let exact = &exact[(x - self.x0) as usize];
+            for (a, e) in attributes.iter().zip(exact.iter()) {
+                error = error.max((a - e).abs());
+            }
+        });
+        error
+    }
+}
+
+/// How attributes are interpolated along a span.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Perspective {
+    /// Divide by 1/z at every pixel.
+    Exact,
+    /// Divide every n pixels and interpolate z and the attributes linearly in between.
+    Subdivided(u32),
+    /// Divide only at both ends of the span.
+    Affine,
+}
+
+impl Span {
+    /// Calls `f` with `(x, z, u, v)` for every pixel of the span.
+    #[inline(always)]
+    pub fn for_each<F>(&self, mut f: F)
+    where
+        F: FnMut(i32, f32, f32, f32),
+    {
+        self.for_each_varyings(|x, z, [u, v]| f(x, z, u, v))
+    }
+
+    /// Like `for_each`, but with the perspective divide done according to `perspective`.
+    #[inline(always)]
+    pub fn for_each_perspective<F>(&self, perspective: Perspective, mut f: F)
+    where
+        F: FnMut(i32, f32, f32, f32),
+    {
+        self.for_each_varyings_perspective(perspective, |x, z, [u, v]| f(x, z, u, v))
+    }
+}
+
+/// Draws spans into framebuffer rows. `pixels` and `zbuffer` are complete rows, indexed by x.
+/// Pixels are palette indices unless `P` says otherwise, e.g. `u32` for truecolor.
+pub trait SpanShader<const N: usize = 2, P = u8> {
+    fn draw_span(
+        &self,
+        span: &Span<N>,
+        pixels: &mut [P],
+        zbuffer: &mut [f32],
+        stats: &mut RasterStats,
+    );
+}
+
+impl<F, P, const N: usize> SpanShader<N, P> for F
+where
+    F: Fn(&Span<N>, &mut [P], &mut [f32], &mut RasterStats),
+{
+    fn draw_span(
+        &self,
+        span: &Span<N>,
+        pixels: &mut [P],
+        zbuffer: &mut [f32],
+        stats: &mut RasterStats,
+    ) {
+        self(span, pixels, zbuffer, stats)
+    }
+}
+
+/// Depth test and depth write for pixel `x` of a zbuffer row, counting rejected and overdrawn
+/// fragments. Returns true if the pixel should be written.
+#[inline(always)]
+pub fn depth_test(zbuffer: &mut [f32], x: i32, z: f32, stats: &mut RasterStats) -> bool {
+    let depth = &mut zbuffer[x as usize];
+    if z > *depth {
+        stats.depth_rejected += 1;
+        return false;
+    }
+    if *depth != f32::MAX {
+        stats.overdraw += 1;
+    }
+    *depth = z;
+    true
+}
+
+/// Like `depth_test`, but leaves the zbuffer alone, e.g. for translucent surfaces.
+#[inline(always)]
+pub fn depth_compare(zbuffer: &[f32], x: i32, z: f32, stats: &mut RasterStats) -> bool {
+    let depth = zbuffer[x as usize];
+    if z > depth {
+        stats.depth_rejected += 1;
+        return false;
+    }
+    if depth != f32::MAX {
+        stats.overdraw += 1;
+    }
+    true
+}
+
+/// Single palette index for the whole polygon.
+#[derive(Debug, Clone, Copy)]
+pub struct FlatShader {
+    pub color: u8,
+}
+
+impl<const N: usize> SpanShader<N> for FlatShader {
+    fn draw_span(
+        &self,
+        span: &Span<N>,
+        pixels: &mut [u8],
+        zbuffer: &mut [f32],
+        stats: &mut RasterStats,
+    ) {
+        span.for_each_varyings(|x, z, _| {
+            if depth_test(zbuffer, x, z, stats) {
+                pixels[x as usize] = self.color;
+            }
+        });
+    }
+}
+
+/// Only fills the zbuffer, e.g. for a depth pre-pass.
+#[derive(Debug, Clone, Copy)]
+pub struct DepthShader;
+
+impl<P, const N: usize> SpanShader<N, P> for DepthShader {
+    fn draw_span(&self, span: &Span<N>, _: &mut [P], zbuffer: &mut [f32], stats: &mut RasterStats) {
+        span.for_each_varyings(|x, z, _| {
+            depth_test(zbuffer, x, z, stats);
+        });
+    }
+}
+
+/// How the mip level of a texture is chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MipSelect {
+    /// Always sample the base level.
+    Base,
+    /// One level for the whole span, from the derivatives at its center.
+    PerSpan,
+    PerPixel,
+}
+
+/// Paletted texture, lit through the colormap. As `SpanShader<3>` the third attribute is the
+/// light value, otherwise surfaces are fully lit.
+#[derive(Debug, Clone, Copy)]
+pub struct TexturedShader<'a> {
+    pub texture: &'a Mipmaps,
+    /// Rows picked by `light`, e.g. from `palette::read_colormap` or `ColormapSpec::generate`.
+    pub colormap: &'a [[u8; NUM_COLORS]],
+    pub wrap: Wrap,
+    pub perspective: Perspective,
+    pub light: LightModel,
+    pub mip_select: MipSelect,
+    /// Draws the mip level with these palette indices instead of the texture.
+    pub mip_debug_colors: Option<&'a [u8]>,
+    pub dither: Option<Dither>,
+    /// Blends over the framebuffer through this table instead of overwriting it. Translucent
+    /// polygons are depth tested but don't write depth, so they are drawn after the opaque ones,
+    /// back to front.
+    pub tranmap: Option<&'a Tranmap>,
+}
+
+impl<'a> TexturedShader<'a> {
+    pub fn new(texture: &'a Mipmaps, colormap: &'a [[u8; NUM_COLORS]]) -> Self {
+        TexturedShader {
+            texture,
+            colormap,
+            wrap: Wrap::Repeat,
+            perspective: Perspective::Exact,
+            light: LightModel::default(),
+            mip_select: MipSelect::Base,
+            mip_debug_colors: None,
+            dither: None,
+            tranmap: None,
+        }
+    }
+
+    #[inline(always)]
+    fn draw<F, const N: usize>(
+        &self,
+        span: &Span<N>,
+        pixels: &mut [u8],
+        zbuffer: &mut [f32],
+        stats: &mut RasterStats,
+        light: F,
+    ) where
+        F: Fn(&[f32; N]) -> f32,
+    {
+        let level = |lod: f32| (lod + 0.5).max(0.0) as usize;
+        let span_level = match self.mip_select {
+            MipSelect::PerSpan => {
+                let (z, attributes) = span.eval((span.x0 + span.x1 - 1) / 2);
+                level(span.uv_lod(z, attributes[0], attributes[1]))
+            }
+            _ => 0,
+        };
+        let transparent = self.texture.base().transparent();
+        span.for_each_varyings_perspective(self.perspective, |x, z, attributes| {
+            let (u, v) = (attributes[0], attributes[1]);
+            let level = match self.mip_select {
+                MipSelect::PerPixel => level(span.uv_lod(z, u, v)),
+                _ => span_level,
+            };
+            let sample = || match self.dither {
+                Some(dither) => {
+                    self.texture
+                        .sample_dithered(level, u, v, dither.offset(x, span.y), self.wrap)
+                }
+                None => self.texture.sample(level, u, v, self.wrap),
+            };
+            // masked textures are sampled before the depth test, transparent texels leave
+            // color and depth alone
+            let texel = match transparent {
+                Some(transparent) => {
+                    let texel = sample();
+                    if texel == transparent {
+                        return;
+                    }
+                    Some(texel)
+                }
+                None => None,
+            };
+            let visible = match self.tranmap {
+                Some(_) => depth_compare(zbuffer, x, z, stats),
+                None => depth_test(zbuffer, x, z, stats),
+            };
+            if !visible {
+                return;
+            }
+            // levels beyond the debug colors get the last one, an empty slice draws the texture
+            let debug_color = self
+                .mip_debug_colors
+                .and_then(|colors| colors.get(level).or(colors.last()));
+            let color = match debug_color {
+                Some(color) => *color,
+                None => {
+                    let texel = texel.unwrap_or_else(sample);
+                    self.colormap[self.light.row(light(&attributes), z)][texel as usize]
+                }
+            };
+            let pixel = &mut pixels[x as usize];
+            *pixel = match self.tranmap {
+                Some(tranmap) => tranmap.blend(color, *pixel),
+                None => color,
+            };
+        });
+    }
+}
+
+impl SpanShader for TexturedShader<'_> {
+    fn draw_span(
+        &self,
+        span: &Span,
+        pixels: &mut [u8],
+        zbuffer: &mut [f32],
+        stats: &mut RasterStats,
+    ) {
+        self.draw(span, pixels, zbuffer, stats, |_| 1.0);
+    }
+}
+
+impl SpanShader<3> for TexturedShader<'_> {
+    fn draw_span(
+        &self,
+        span: &Span<3>,
+        pixels: &mut [u8],
+        zbuffer: &mut [f32],
+        stats: &mut RasterStats,
+    ) {
+        self.draw(span, pixels, zbuffer, stats, |attributes| attributes[2]);
+    }
+}
+
+/// 32 bit texture drawn into a truecolor framebuffer, for comparing filtering with the dithered
+/// palette path.
+#[derive(Debug, Clone, Copy)]
+pub struct TruecolorShader<'a> {
+    pub texture: &'a Mipmaps<u32>,
+    pub wrap: Wrap,
+    pub perspective: Perspective,
+    pub filter: Filter,
+    /// Only applies to nearest sampling.
+    pub dither: Option<Dither>,
+}
+
+impl<'a> TruecolorShader<'a> {
+    pub fn new(texture: &'a Mipmaps<u32>) -> Self {
+        TruecolorShader {
+            texture,
+            wrap: Wrap::Repeat,
+            perspective: Perspective::Exact,
+            filter: Filter::Nearest,
+            dither: None,
+        }
+    }
+}
+
+impl SpanShader<2, u32> for TruecolorShader<'_> {
+    fn draw_span(
+        &self,
+        span: &Span,
+        pixels: &mut [u32],
+        zbuffer: &mut [f32],
+        stats: &mut RasterStats,
+    ) {
+        span.for_each_varyings_perspective(self.perspective, |x, z, [u, v]| {
+            if !depth_test(zbuffer, x, z, stats) {
+                return;
+            }
+            pixels[x as usize] = match (self.filter, self.dither) {
+                (Filter::Nearest, Some(dither)) => {
+                    self.texture
+                        .sample_dithered(0, u, v, dither.offset(x, span.y), self.wrap)
+                }
+                (Filter::Trilinear, _) => {
+                    self.texture
+                        .sample_trilinear(span.uv_lod(z, u, v), u, v, self.wrap)
+                }
+                (filter, _) => self.texture.sample_filtered(filter, 0.0, u, v, self.wrap),
+            };
+        });
+    }
+}
+
+/// Draws a polygon into `fb` span by span.
+pub fn draw_polygon<V, S, const N: usize>(
+    fb: &mut Framebuffer,
+    points: &[V],
+    fill_rule: FillRule,
+    scissor: &Scissor,
+    stats: &mut RasterStats,
+    shader: &S,
+) where
+    V: Varyings<N>,
+    S: SpanShader<N> + ?Sized,
+{
+    let width = fb.width() as usize;
+    let scissor = scissor.intersect(&Scissor::from_size(fb.width(), fb.height()));
+    let mut shader_stats = RasterStats::default();
+    texpoly::draw_polygon_spans(
+        points,
+        fill_rule,
+        &scissor,
+        i32::MIN..i32::MAX,
+        stats,
+        |span| {
+            let row = span.y as usize * width..(span.y as usize + 1) * width;
+            shader.draw_span(
+                span,
+                &mut fb.framebuffer[row.clone()],
+                &mut fb.zbuffer[row],
+                &mut shader_stats,
+            );
+        },
+    );
+    *stats += shader_stats;
+}
+
+#[test]
+fn test_span_matches_fragments() {
+    let palette = [0u32; NUM_COLORS];
+    let poly = [
+        (3.2, 2.7, 20.0, 0.0, 0.0),
+        (60.1, 20.3, 40.0, 64.0, 0.0),
+        (40.4, 61.9, 80.0, 64.0, 64.0),
+        (5.5, 50.0, 30.0, 0.0, 64.0),
+    ];
+    let scissor = Scissor::new(4, 5, 50, 60);
+    for fill_rule in [FillRule::Truncate, FillRule::TopLeft] {
+        let mut reference = Framebuffer::new(64, 64, &palette);
+        let mut reference_stats = RasterStats::default();
+        texpoly::draw_polygon(
+            &poly,
+            fill_rule,
+            &scissor,
+            &mut reference_stats,
+            |x, y, z, u, v, _| {
+                let pixel_index = (y * 64 + x) as usize;
+                reference.zbuffer[pixel_index] = z;
+                reference.framebuffer[pixel_index] = (u as u8) ^ (v as u8);
+            },
+        );
+
+        let mut fb = Framebuffer::new(64, 64, &palette);
+        let mut stats = RasterStats::default();
+        let shader =
+            |span: &Span, pixels: &mut [u8], zbuffer: &mut [f32], stats: &mut RasterStats| {
+                span.for_each(|x, z, u, v| {
+                    if depth_test(zbuffer, x, z, stats) {
+                        pixels[x as usize] = (u as u8) ^ (v as u8);
+                    }
+                });
+            };
+        draw_polygon(&mut fb, &poly, fill_rule, &scissor, &mut stats, &shader);
+        assert_eq!(stats, reference_stats);
+        assert_eq!(fb.framebuffer, reference.framebuffer);
+        assert!(fb
+            .zbuffer
+            .iter()
+            .zip(reference.zbuffer.iter())
+            .all(|(a, b)| a.to_bits() == b.to_bits()));
+    }
+}
+
+#[test]
+fn test_subdivided_perspective() {
+    let span = Span {
+        y: 0,
+        x0: 3,
+        x1: 103,
+        zi: 1.0 / 2.0,
+        zi_step: -0.004,
+        zi_dy: 0.0,
+        start: [0.0, 0.0],
+        step: [0.32, 0.05],
+        dy: [0.0, 0.0],
+        aux: 0,
+    };
+    let mut exact = Vec::new();
+    span.for_each(|x, z, u, v| exact.push((x, z, u, v)));
+    assert_eq!(span.texel_error(Perspective::Exact), 0.0);
+    // divides at every pixel, only the rounding differs
+    assert!(span.texel_error(Perspective::Subdivided(1)) < 1e-3);
+
+    let mut last_error = 0.0;
+    for perspective in [
+        Perspective::Subdivided(8),
+        Perspective::Subdivided(16),
+        Perspective::Affine,
+    ] {
+        let mut pixels = Vec::new();
+        span.for_each_perspective(perspective, |x, z, u, v| pixels.push((x, z, u, v)));
+        assert_eq!(pixels.len(), exact.len());
+        for (p, e) in pixels.iter().zip(exact.iter()) {
+            assert_eq!(p.0, e.0);
+        }
+        // both ends of the span are exact
+        let (first, last) = (pixels[0], pixels[pixels.len() - 1]);
+        assert_eq!(first, exact[0]);
+        assert!((last.2 - exact[exact.len() - 1].2).abs() < 1e-3);
+
+        let error = span.texel_error(perspective);
+        assert!(
+            error > last_error,
+            "{:?}: {} {}",
+            perspective,
+            error,
+            last_error
+        );
+        last_error = error;
+    }
+}
+
+#[test]
+fn test_uv_lod() {
+    // 64 texels across 16 pixels horizontally and 8 pixels vertically
+    let poly = [
+        (4.0, 4.0, 10.0, 0.0, 0.0),
+        (20.0, 4.0, 10.0, 64.0, 0.0),
+        (20.0, 12.0, 10.0, 64.0, 64.0),
+        (4.0, 12.0, 10.0, 0.0, 64.0),
+    ];
+    let mut stats = RasterStats::default();
+    let mut num_spans = 0;
+    texpoly::draw_polygon_spans(
+        &poly,
+        FillRule::TopLeft,
+        &Scissor::from_size(32, 32),
+        i32::MIN..i32::MAX,
+        &mut stats,
+        |span| {
+            span.for_each(|_, z, u, v| {
+                assert!((span.uv_lod(z, u, v) - 3.0).abs() < 1e-4);
+            });
+            num_spans += 1;
+        },
+    );
+    assert_eq!(num_spans, 8);
+}
+
+#[test]
+fn test_masked_texture() {
+    let palette = [0u32; NUM_COLORS];
+    let colormap = crate::test_fixtures::identity_colormap(64);
+    // left half opaque, right half transparent
+    let texture =
+        Mipmaps::single(crate::texture::Texture::new(2, 1, vec![7, 255]).with_transparent(255));
+    let poly = [
+        (0.0, 0.0, 10.0, 0.0, 0.0),
+        (16.0, 0.0, 10.0, 2.0, 0.0),
+        (16.0, 16.0, 10.0, 2.0, 1.0),
+        (0.0, 16.0, 10.0, 0.0, 1.0),
+    ];
+    let mut fb = Framebuffer::new(16, 16, &palette);
+    fb.framebuffer.fill(1);
+    let mut stats = RasterStats::default();
+    let shader = TexturedShader::new(&texture, &colormap);
+    draw_polygon(
+        &mut fb,
+        &poly,
+        FillRule::TopLeft,
+        &Scissor::from_size(16, 16),
+        &mut stats,
+        &shader,
+    );
+    for y in 0..16 {
+        for x in 0..16 {
+            let i = y * 16 + x;
+            if x < 8 {
+                assert_eq!(fb.framebuffer[i], 7);
+                assert_eq!(fb.zbuffer[i], 10.0);
+            } else {
+                assert_eq!(fb.framebuffer[i], 1);
+                assert_eq!(fb.zbuffer[i], f32::MAX);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_mip_debug_colors() {
+    let palette = [0u32; NUM_COLORS];
+    let colormap = [[7u8; NUM_COLORS]; 64];
+    let texture = Mipmaps::single(crate::texture::Texture::new(1, 1, vec![0]));
+    let poly = [
+        (0.0, 0.0, 10.0, 0.0, 0.0),
+        (4.0, 0.0, 10.0, 1.0, 0.0),
+        (4.0, 4.0, 10.0, 1.0, 1.0),
+        (0.0, 4.0, 10.0, 0.0, 1.0),
+    ];
+    // an empty list of debug colors falls back to the texture
+    for (colors, expected) in [(&[3u8, 4][..], 3), (&[][..], 7)] {
+        let mut fb = Framebuffer::new(4, 4, &palette);
+        let shader = TexturedShader {
+            mip_debug_colors: Some(colors),
+            ..TexturedShader::new(&texture, &colormap)
+        };
+        draw_polygon(
+            &mut fb,
+            &poly,
+            FillRule::TopLeft,
+            &Scissor::from_size(4, 4),
+            &mut RasterStats::default(),
+            &shader,
+        );
+        assert!(fb.framebuffer.iter().all(|p| *p == expected));
+    }
+}
+
+#[test]
+fn test_translucent() {
+    let palette = crate::test_fixtures::gray_palette();
+    let colormap = crate::test_fixtures::identity_colormap(64);
+    let tranmap = Tranmap::new(&palette, 0.25);
+    let texture = Mipmaps::single(crate::texture::Texture::new(1, 1, vec![200]));
+    let poly = [
+        (0.0, 0.0, 10.0, 0.0, 0.0),
+        (16.0, 0.0, 10.0, 1.0, 0.0),
+        (16.0, 16.0, 10.0, 1.0, 1.0),
+        (0.0, 16.0, 10.0, 0.0, 1.0),
+    ];
+    let mut fb = Framebuffer::new(16, 16, &palette);
+    fb.framebuffer.fill(100);
+    // the bottom half is in front of the polygon
+    for depth in fb.zbuffer[8 * 16..].iter_mut() {
+        *depth = 5.0;
+    }
+    let shader = TexturedShader {
+        tranmap: Some(&tranmap),
+        ..TexturedShader::new(&texture, &colormap)
+    };
+    let mut stats = RasterStats::default();
+    draw_polygon(
+        &mut fb,
+        &poly,
+        FillRule::TopLeft,
+        &Scissor::from_size(16, 16),
+        &mut stats,
+        &shader,
+    );
+    assert!(fb.framebuffer[..8 * 16].iter().all(|p| *p == 125));
+    assert!(fb.framebuffer[8 * 16..].iter().all(|p| *p == 100));
+    assert!(fb.zbuffer[..8 * 16].iter().all(|z| *z == f32::MAX));
+    assert_eq!(stats.depth_rejected, 8 * 16);
+}
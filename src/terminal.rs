@@ -0,0 +1,111 @@
+use std::{
+    io::{self, Read, Write},
+    mem::MaybeUninit,
+};
+
+/// Key read from a raw terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    Escape,
+}
+
+/// Puts the terminal into raw mode on the alternate screen with the cursor hidden, so frames
+/// from `present::Ansi` replace each other and keys arrive without echo or line buffering.
+/// Everything is restored on drop.
+pub struct RawTerminal {
+    original: libc::termios,
+}
+
+impl RawTerminal {
+    pub fn new() -> io::Result<Self> {
+        let mut termios = MaybeUninit::uninit();
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let original = unsafe { termios.assume_init() };
+        let mut raw = original;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        // reads return immediately, with or without input
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        print!("\x1b[?1049h\x1b[?25l\x1b[2J");
+        io::stdout().flush()?;
+        Ok(RawTerminal { original })
+    }
+
+    /// Size in character cells as columns and rows.
+    pub fn size(&self) -> Option<(u32, u32)> {
+        let mut size = MaybeUninit::<libc::winsize>::uninit();
+        if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, size.as_mut_ptr()) } != 0 {
+            return None;
+        }
+        let size = unsafe { size.assume_init() };
+        Some((size.ws_col as u32, size.ws_row as u32))
+    }
+
+    /// Keys pressed since the last call, without blocking.
+    pub fn keys(&mut self) -> Vec<Key> {
+        let mut buf = [0u8; 64];
+        let mut input = Vec::new();
+        while let Ok(n @ 1..) = io::stdin().read(&mut buf) {
+            input.extend_from_slice(&buf[..n]);
+        }
+        parse_keys(&input)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        print!("\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+    }
+}
+
+// arrow keys come as escape sequences, anything else starting with escape is dropped
+fn parse_keys(input: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let text = String::from_utf8_lossy(input);
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let key = match c {
+            '\x1b' if chars.peek() == Some(&'[') => {
+                chars.next();
+                match chars.next() {
+                    Some('A') => Key::Up,
+                    Some('B') => Key::Down,
+                    Some('C') => Key::Right,
+                    Some('D') => Key::Left,
+                    _ => continue,
+                }
+            }
+            '\x1b' => Key::Escape,
+            c => Key::Char(c),
+        };
+        keys.push(key);
+    }
+    keys
+}
+
+#[test]
+fn test_parse_keys() {
+    assert_eq!(
+        parse_keys(b"w\x1b[D\x1b[Ca\x1b\x1b[Zq"),
+        [
+            Key::Char('w'),
+            Key::Left,
+            Key::Right,
+            Key::Char('a'),
+            Key::Escape,
+            Key::Char('q'),
+        ]
+    );
+}
@@ -1,56 +1,428 @@
+use std::ops::Range;
+
 use crate::{
-    rasterize::{rasterize_polygon, Slope},
+    rasterize::{rasterize_polygon, FillRule, RasterStats, Scissor, Slope},
     slope::SlopeData,
+    span::Span,
 };
 
 // type Point = [i32; 5];
-type Point = (f32, f32, f32, f32, f32);
+pub type Point = (f32, f32, f32, f32, f32);
+
+/// Projected vertex with `N` attributes that are interpolated perspective correctly.
+pub trait Varyings<const N: usize> {
+    /// Screen x, y and view space z.
+    fn position(&self) -> (f32, f32, f32);
+    fn attributes(&self) -> [f32; N];
+}
+
+impl Varyings<2> for Point {
+    #[inline(always)]
+    fn position(&self) -> (f32, f32, f32) {
+        (self.0, self.1, self.2)
+    }
+    #[inline(always)]
+    fn attributes(&self) -> [f32; 2] {
+        [self.3, self.4]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vertex<const N: usize> {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub attributes: [f32; N],
+}
+
+impl<const N: usize> Varyings<N> for Vertex<N> {
+    #[inline(always)]
+    fn position(&self) -> (f32, f32, f32) {
+        (self.x, self.y, self.z)
+    }
+    #[inline(always)]
+    fn attributes(&self) -> [f32; N] {
+        self.attributes
+    }
+}
 
-pub fn draw_polygon<F>(points: &[Point], mut fragment: F)
-where
+/// Value of a linear function of the screen position at some origin and its change per pixel.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Plane {
+    pub value: f32,
+    pub dx: f32,
+    pub dy: f32,
+}
+
+/// Screen space gradients of 1/z and of every attribute divided by z. These are linear in x and y
+/// over a planar polygon, so they are set up once per polygon instead of being interpolated along
+/// edges and spans.
+#[derive(Debug, Clone, Copy)]
+pub struct Gradients<const N: usize = 2> {
+    pub origin: (f32, f32),
+    pub zi: Plane,
+    pub attributes: [Plane; N],
+}
+
+impl<const N: usize> Gradients<N> {
+    pub fn new<V: Varyings<N>>(points: &[V]) -> Self {
+        let setup = |p: &V| {
+            let (x, y, z) = p.position();
+            let zi = 1.0 / z;
+            (x, y, zi, p.attributes().map(|a| a * zi))
+        };
+        let Some(p0) = points.first().map(setup) else {
+            return Gradients {
+                origin: (0.0, 0.0),
+                zi: Plane::default(),
+                attributes: [Plane::default(); N],
+            };
+        };
+        let flat = |value| Plane {
+            value,
+            dx: 0.0,
+            dy: 0.0,
+        };
+        let mut gradients = Gradients {
+            origin: (p0.0, p0.1),
+            zi: flat(p0.2),
+            attributes: p0.3.map(flat),
+        };
+
+        // the largest triangle of the fan gives the best conditioned setup
+        let cross = |p1: &V, p2: &V| {
+            let (x1, y1, _) = p1.position();
+            let (x2, y2, _) = p2.position();
+            (x1 - p0.0) * (y2 - p0.1) - (x2 - p0.0) * (y1 - p0.1)
+        };
+        let Some((p1, p2)) = points
+            .windows(2)
+            .skip(1)
+            .map(|w| (&w[0], &w[1]))
+            .max_by(|a, b| cross(a.0, a.1).abs().total_cmp(&cross(b.0, b.1).abs()))
+        else {
+            return gradients;
+        };
+        let area = cross(p1, p2);
+        if area == 0.0 {
+            return gradients;
+        }
+        let inv_area = 1.0 / area;
+        let (p1, p2) = (setup(p1), setup(p2));
+        let (dx1, dy1) = (p1.0 - p0.0, p1.1 - p0.1);
+        let (dx2, dy2) = (p2.0 - p0.0, p2.1 - p0.1);
+        let solve = |plane: &mut Plane, a1: f32, a2: f32| {
+            let d1 = a1 - plane.value;
+            let d2 = a2 - plane.value;
+            plane.dx = (d1 * dy2 - d2 * dy1) * inv_area;
+            plane.dy = (d2 * dx1 - d1 * dx2) * inv_area;
+        };
+        solve(&mut gradients.zi, p1.2, p2.2);
+        for (i, plane) in gradients.attributes.iter_mut().enumerate() {
+            solve(plane, p1.3[i], p2.3[i]);
+        }
+        gradients
+    }
+
+    /// 1/z and the attributes divided by z at screen position `(x, y)`.
+    #[inline(always)]
+    pub fn eval(&self, x: f32, y: f32) -> (f32, [f32; N]) {
+        let (x, y) = (x - self.origin.0, y - self.origin.1);
+        let eval = |plane: &Plane| plane.value + plane.dx * x + plane.dy * y;
+        (eval(&self.zi), self.attributes.each_ref().map(eval))
+    }
+}
+
+/// Draws a polygon clipped to `scissor`, calling `fragment` with `(x, y, z, u, v, aux)` for every
+/// covered pixel.
+pub fn draw_polygon<F>(
+    points: &[Point],
+    fill_rule: FillRule,
+    scissor: &Scissor,
+    stats: &mut RasterStats,
+    mut fragment: F,
+) where
     F: FnMut(i32, i32, f32, f32, f32, u32),
 {
-    // let points = [p0, p1, p2];
+    draw_polygon_rows(
+        points,
+        fill_rule,
+        scissor,
+        i32::MIN..i32::MAX,
+        stats,
+        |x, y, z, [u, v], aux| fragment(x, y, z, u, v, aux),
+    )
+}
+
+/// Like `draw_polygon`, but for any vertex type. `fragment` is called with
+/// `(x, y, z, attributes, aux)`.
+pub fn draw_polygon_varyings<V, F, const N: usize>(
+    points: &[V],
+    fill_rule: FillRule,
+    scissor: &Scissor,
+    stats: &mut RasterStats,
+    fragment: F,
+) where
+    V: Varyings<N>,
+    F: FnMut(i32, i32, f32, [f32; N], u32),
+{
+    draw_polygon_rows(
+        points,
+        fill_rule,
+        scissor,
+        i32::MIN..i32::MAX,
+        stats,
+        fragment,
+    )
+}
+
+/// Like `draw_polygon_varyings`, but only emits fragments for scanlines within `rows`. Edges are
+/// still stepped through the skipped scanlines, so the fragments are exactly those
+/// `draw_polygon_varyings` produces for these rows.
+pub fn draw_polygon_rows<V, F, const N: usize>(
+    points: &[V],
+    fill_rule: FillRule,
+    scissor: &Scissor,
+    rows: Range<i32>,
+    stats: &mut RasterStats,
+    mut fragment: F,
+) where
+    V: Varyings<N>,
+    F: FnMut(i32, i32, f32, [f32; N], u32),
+{
+    draw_polygon_spans(points, fill_rule, scissor, rows, stats, |span| {
+        span.for_each_varyings(|x, z, attributes| fragment(x, span.y, z, attributes, span.aux));
+    })
+}
+
+/// Rasterizes a polygon into non-empty spans. Attributes are handed out divided by z, so they can
+/// be interpolated linearly along the span. Only x is stepped along the edges, the attributes come
+/// from the polygon's `Gradients`.
+pub fn draw_polygon_spans<V, F, const N: usize>(
+    points: &[V],
+    fill_rule: FillRule,
+    scissor: &Scissor,
+    rows: Range<i32>,
+    stats: &mut RasterStats,
+    mut draw_span: F,
+) where
+    V: Varyings<N>,
+    F: FnMut(&Span<N>),
+{
+    let gradients = Gradients::new(points);
+    let mut num_fragments = 0;
     rasterize_polygon(
-        &points,
-        |p| (p.0, p.1),
+        points,
+        fill_rule,
+        scissor,
+        stats,
+        |p| {
+            let (x, y, _) = p.position();
+            (x, y)
+        },
         // slope generator
-        |from, to, num_steps| {
-            let zbegin = 1.0 / from.2;
-            let zend = 1.0 / to.2;
-            let result = [
-                SlopeData::new(from.0, to.0, num_steps),
-                SlopeData::new(zbegin, zend, num_steps), // inverted z coordinate
-                SlopeData::new(from.3 * zbegin, to.3 * zend, num_steps),
-                SlopeData::new(from.4 * zbegin, to.4 * zend, num_steps),
-                // SlopeData::new(from.4, to.4, num_steps),
-            ];
-            result
+        |from, to, num_steps, prestep| {
+            let mut x = SlopeData::new(from.position().0, to.position().0, num_steps);
+            x.prestep(prestep);
+            x
         },
         //scanline function
         |y, left, right, aux| {
-            let xstart = left[0].get();
-            let xend = right[0].get();
-
-            let num_steps = xend - xstart;
-            let mut props = [
-                SlopeData::new(left[1].get(), right[1].get(), num_steps),
-                SlopeData::new(left[2].get(), right[2].get(), num_steps),
-                SlopeData::new(left[3].get(), right[3].get(), num_steps),
-            ];
-            for x in (xstart as i32)..(xend as i32) {
-                let z = 1.0 / props[0].get();
-                fragment(x, y, z, props[1].get() * z, props[2].get() * z, aux);
-                for prop in props.iter_mut() {
-                    prop.advance();
+            if rows.contains(&y) {
+                let xstart = fill_rule.to_pixel(left.get()).max(scissor.x0);
+                let xend = fill_rule.to_pixel(right.get()).min(scissor.x1);
+
+                if xstart < xend {
+                    num_fragments += (xend - xstart) as u64;
+                    let (zi, start) =
+                        gradients.eval(fill_rule.sample_point(xstart), fill_rule.sample_point(y));
+                    draw_span(&Span {
+                        y,
+                        x0: xstart,
+                        x1: xend,
+                        zi,
+                        zi_step: gradients.zi.dx,
+                        zi_dy: gradients.zi.dy,
+                        start,
+                        step: gradients.attributes.map(|plane| plane.dx),
+                        dy: gradients.attributes.map(|plane| plane.dy),
+                        aux,
+                    });
                 }
             }
-            for border in left.iter_mut() {
-                border.advance();
-            }
-            for border in right.iter_mut() {
-                border.advance();
+            left.advance();
+            right.advance();
+        },
+    );
+    stats.fragments += num_fragments;
+}
+
+#[test]
+fn test_top_left_watertight() {
+    const S: usize = 64;
+    let corners = [(10.3, 9.8), (50.7, 10.2), (51.2, 50.6), (9.6, 49.9)];
+    let center = (30.1, 29.6);
+
+    let mut coverage = [[0u32; S]; S];
+    for i in 0..corners.len() {
+        let a = corners[i];
+        let b = corners[(i + 1) % corners.len()];
+        let poly = [
+            (a.0, a.1, 1.0, 0.0, 0.0),
+            (b.0, b.1, 1.0, 0.0, 0.0),
+            (center.0, center.1, 1.0, 0.0, 0.0),
+        ];
+        let mut stats = RasterStats::default();
+        let scissor = Scissor::from_size(S as u32, S as u32);
+        draw_polygon(
+            &poly,
+            FillRule::TopLeft,
+            &scissor,
+            &mut stats,
+            |x, y, _, _, _, _| {
+                coverage[y as usize][x as usize] += 1;
+            },
+        );
+    }
+
+    // none of the pixel centers lie exactly on an edge, so coverage is a plain inside test
+    let inside = |px: f32, py: f32| {
+        (0..corners.len()).all(|i| {
+            let (ax, ay) = corners[i];
+            let (bx, by) = corners[(i + 1) % corners.len()];
+            (bx - ax) * (py - ay) - (by - ay) * (px - ax) > 0.0
+        })
+    };
+    for (y, line) in coverage.iter().enumerate() {
+        for (x, count) in line.iter().enumerate() {
+            let expected = inside(x as f32 + 0.5, y as f32 + 0.5) as u32;
+            assert_eq!(*count, expected, "pixel {} {}", x, y);
+        }
+    }
+}
+
+#[test]
+fn test_scissor() {
+    const S: usize = 64;
+    let poly = [
+        (-20.3, -10.2, 2.0, 0.0, 0.0),
+        (80.7, 5.1, 4.0, 64.0, 0.0),
+        (70.2, 90.6, 8.0, 64.0, 64.0),
+        (-5.6, 60.9, 3.0, 0.0, 64.0),
+    ];
+    let scissor = Scissor::new(7, 11, 50, 40);
+    for fill_rule in [FillRule::Truncate, FillRule::TopLeft] {
+        let mut reference = [[None; S]; S];
+        let mut stats = RasterStats::default();
+        let unbounded = Scissor::new(-100, -100, 100, 100);
+        draw_polygon(
+            &poly,
+            fill_rule,
+            &unbounded,
+            &mut stats,
+            |x, y, z, u, v, _| {
+                if scissor.contains(x, y) {
+                    reference[y as usize][x as usize] = Some((z, u, v));
+                }
+            },
+        );
+        let mut clipped = [[None; S]; S];
+        draw_polygon(
+            &poly,
+            fill_rule,
+            &scissor,
+            &mut stats,
+            |x, y, z, u, v, _| {
+                assert!(scissor.contains(x, y));
+                clipped[y as usize][x as usize] = Some((z, u, v));
+            },
+        );
+        for (line_r, line_c) in reference.iter().zip(clipped.iter()) {
+            for (r, c) in line_r.iter().zip(line_c.iter()) {
+                match (r, c) {
+                    (None, None) => (),
+                    (Some(r), Some(c)) => {
+                        assert!((r.0 - c.0).abs() < 1e-3 * r.0);
+                        assert!((r.1 - c.1).abs() < 1e-2);
+                        assert!((r.2 - c.2).abs() < 1e-2);
+                    }
+                    _ => panic!("coverage differs: {:?} {:?}", r, c),
+                }
             }
+        }
+    }
+}
+
+#[test]
+fn test_gradients_narrow_polygon() {
+    // sliver whose spans are mostly a single pixel wide, far away and steeply inclined
+    let poly = [
+        (20.2, 1.3, 50.0, 0.0, 0.0),
+        (21.1, 1.6, 400.0, 256.0, 0.0),
+        (22.4, 60.7, 420.0, 256.0, 512.0),
+    ];
+    // the setup reproduces the vertices
+    let gradients = Gradients::new(&poly);
+    for p in poly.iter() {
+        let (zi, [u, v]) = gradients.eval(p.0, p.1);
+        assert!((1.0 / zi - p.2).abs() < 1e-2 * p.2);
+        assert!((u / zi - p.3).abs() < 1e-1);
+        assert!((v / zi - p.4).abs() < 1e-1);
+    }
+    let scissor = Scissor::from_size(64, 64);
+    let mut stats = RasterStats::default();
+    let mut num_fragments = 0;
+    draw_polygon(
+        &poly,
+        FillRule::TopLeft,
+        &scissor,
+        &mut stats,
+        |x, y, z, u, v, _| {
+            let (zi, [uz, vz]) = gradients.eval(x as f32 + 0.5, y as f32 + 0.5);
+            assert_eq!(z, 1.0 / zi);
+            assert_eq!(u, uz * z);
+            assert_eq!(v, vz * z);
+            num_fragments += 1;
         },
-    )
+    );
+    assert!(num_fragments > 0);
+}
+
+#[test]
+fn test_varyings() {
+    let poly = [
+        (3.2, 2.7, 2.0, 0.0, 0.0),
+        (60.1, 20.3, 4.0, 64.0, 0.0),
+        (40.4, 61.9, 8.0, 64.0, 64.0),
+    ];
+    // view space z as an extra attribute comes out as the fragment depth
+    let vertices = poly.map(|p| Vertex {
+        x: p.0,
+        y: p.1,
+        z: p.2,
+        attributes: [p.3, p.4, p.2],
+    });
+    let scissor = Scissor::from_size(64, 64);
+    let mut stats = RasterStats::default();
+    let mut fragments = Vec::new();
+    draw_polygon(
+        &poly,
+        FillRule::TopLeft,
+        &scissor,
+        &mut stats,
+        |x, y, z, u, v, _| fragments.push((x, y, z, u, v)),
+    );
+    let mut i = 0;
+    draw_polygon_varyings(
+        &vertices,
+        FillRule::TopLeft,
+        &scissor,
+        &mut stats,
+        |x, y, z, [u, v, w], _| {
+            assert_eq!(fragments[i], (x, y, z, u, v));
+            assert!((w - z).abs() < 1e-4 * z);
+            i += 1;
+        },
+    );
+    assert_eq!(i, fragments.len());
 }
@@ -1,8 +1,9 @@
 use glam::Vec4;
 
 use crate::{
-    rasterize::{rasterize_polygon, Slope},
-    slope::{Slope4x, SlopeData},
+    rasterize::{rasterize_polygon, FillRule, RasterStats, Scissor, Slope},
+    slope::{Slope4x, SlopeData, SlopeNx},
+    texpoly::Varyings,
 };
 
 // #[derive(Debug, Default)]
@@ -29,75 +30,137 @@ use crate::{
 //     }
 // }
 
-// type Point = [i32; 5];
-type Point = (f32, f32, f32, f32, f32);
+use crate::texpoly::Point;
 
-pub fn draw_polygon<F>(points: &[Point], mut fragment: F)
-where
+pub fn draw_polygon<F>(
+    points: &[Point],
+    fill_rule: FillRule,
+    scissor: &Scissor,
+    stats: &mut RasterStats,
+    mut fragment: F,
+) where
     F: FnMut(i32, i32, f32, f32, f32, u32),
 {
+    draw_polygon_varyings(points, fill_rule, scissor, stats, |x, y, z, [u, v], aux| {
+        fragment(x, y, z, u, v, aux)
+    })
+}
+
+/// Same as `texpoly::draw_polygon_varyings`, but interpolates along edges and spans with the
+/// four-wide `Slope4x` / `SlopeNx`.
+pub fn draw_polygon_varyings<V, F, const N: usize>(
+    points: &[V],
+    fill_rule: FillRule,
+    scissor: &Scissor,
+    stats: &mut RasterStats,
+    mut fragment: F,
+) where
+    V: Varyings<N>,
+    F: FnMut(i32, i32, f32, [f32; N], u32),
+{
+    let mut num_fragments = 0;
     // let points = [p0, p1, p2];
     rasterize_polygon(
-        &points,
-        |p| (p.0, p.1),
+        points,
+        fill_rule,
+        scissor,
+        stats,
+        |p| {
+            let (x, y, _) = p.position();
+            (x, y)
+        },
         // slope generator
-        |from, to, num_steps| {
-            let zbegin = 1.0 / from.2;
-            let zend = 1.0 / to.2;
+        |from, to, num_steps, prestep| {
+            let (xbegin, _, zbegin) = from.position();
+            let (xend, _, zend) = to.position();
+            let zbegin = 1.0 / zbegin;
+            let zend = 1.0 / zend;
 
-            Slope4x::new(
-                Vec4::new(from.0, zbegin, from.3 * zbegin, from.4 * zbegin),
-                Vec4::new(to.0, zend, to.3 * zend, to.4 * zend),
+            let mut position = Slope4x::new(
+                Vec4::new(xbegin, zbegin, 0.0, 0.0),
+                Vec4::new(xend, zend, 0.0, 0.0),
                 Vec4::splat(num_steps),
-            )
-            // let result = [
-            //     SlopeData::new(from.0, to.0, num_steps),
-            //     SlopeData::new(zbegin, zend, num_steps), // inverted z coordinate
-            //     SlopeData::new(from.3 * zbegin, to.3 * zend, num_steps),
-            //     SlopeData::new(from.4 * zbegin, to.4 * zend, num_steps),
-            //     // SlopeData::new(from.4, to.4, num_steps),
-            // ];
-            // result
+            );
+            let mut attributes = SlopeNx::new(
+                from.attributes().map(|a| a * zbegin),
+                to.attributes().map(|a| a * zend),
+                num_steps,
+            );
+            position.prestep(prestep);
+            attributes.prestep(prestep);
+            (position, attributes)
         },
         //scanline function
-        |y, left, right, aux| {
-            let left_v = left.get();
-            let right_v = right.get();
+        |y, (left_position, left_attributes), (right_position, right_attributes), aux| {
+            let left_v = left_position.get();
+            let right_v = right_position.get();
 
-            let xstart = left_v.x;
-            let xend = right_v.x;
+            let xstart = fill_rule.to_pixel(left_v.x).max(scissor.x0);
+            let xend = fill_rule.to_pixel(right_v.x).min(scissor.x1);
 
-            let num_steps = xend - xstart;
-            let mut props = Slope4x::new(
-                Vec4::new(left_v.y, left_v.z, left_v.w, 0.0),
-                Vec4::new(right_v.y, right_v.z, right_v.w, 0.0),
-                Vec4::splat(num_steps),
-            );
-
-            // let `mut props = [
-            //     SlopeData::new(left[1].get(), right[1].get(), num_steps),
-            //     SlopeData::new(left[2].get(), right[2].get(), num_steps),
-            //     SlopeData::new(left[3].get(), right[3].get(), num_steps),
-            // ];
-            for x in (xstart as i32)..(xend as i32) {
-                let props_v = props.get();
+            let num_steps = right_v.x - left_v.x;
+            let prestep = fill_rule.prestep(left_v.x, xstart);
+            let mut zi = SlopeData::new(left_v.y, right_v.y, num_steps);
+            let mut props = SlopeNx::new(left_attributes.get(), right_attributes.get(), num_steps);
+            zi.prestep(prestep);
+            props.prestep(prestep);
 
-                let z = 1.0 / props_v.x;
-                let props_z = Vec4::splat(z) * props_v;
-                fragment(x, y, z, props_z.y, props_z.z, aux);
-                // for prop in props.iter_mut() {
-                //     prop.advance();
-                // }
+            num_fragments += (xend - xstart).max(0) as u64;
+            for x in xstart..xend {
+                let z = 1.0 / zi.get();
+                fragment(x, y, z, props.get().map(|a| a * z), aux);
+                zi.advance();
                 props.advance();
             }
-            left.advance();
-            right.advance();
-            // for border in left.iter_mut() {
-            //     border.advance();
-            // }
-            // for border in right.iter_mut() {
-            //     border.advance();
-            // }
+            left_position.advance();
+            left_attributes.advance();
+            right_position.advance();
+            right_attributes.advance();
+        },
+    );
+    stats.fragments += num_fragments;
+}
+
+#[test]
+fn test_matches_scalar() {
+    use crate::texpoly::{self, Vertex};
+    // five attributes fill one Vec4 and leave one in the remainder
+    let vertices = [
+        (3.2, 2.7, 2.0, [0.0, 0.0, 1.0, 5.0, -3.0]),
+        (60.1, 20.3, 4.0, [64.0, 0.0, 2.0, 6.0, -2.0]),
+        (40.4, 61.9, 8.0, [64.0, 64.0, 3.0, 7.0, -1.0]),
+    ]
+    .map(|(x, y, z, attributes)| Vertex {
+        x,
+        y,
+        z,
+        attributes,
+    });
+    let scissor = Scissor::from_size(64, 64);
+    let mut stats = RasterStats::default();
+    let mut scalar = Vec::new();
+    texpoly::draw_polygon_varyings(
+        &vertices,
+        FillRule::TopLeft,
+        &scissor,
+        &mut stats,
+        |x, y, z, attributes, _| scalar.push((x, y, z, attributes)),
+    );
+    let mut i = 0;
+    draw_polygon_varyings(
+        &vertices,
+        FillRule::TopLeft,
+        &scissor,
+        &mut stats,
+        |x, y, z, attributes, _| {
+            let (sx, sy, sz, sa) = scalar[i];
+            assert_eq!((x, y), (sx, sy));
+            assert!((z - sz).abs() < 1e-3 * z);
+            for (a, sa) in attributes.iter().zip(sa.iter()) {
+                assert!((a - sa).abs() < 1e-2, "{:?} {:?}", attributes, sa);
+            }
+            i += 1;
         },
-    )
+    );
+    assert_eq!(i, scalar.len());
 }
@@ -0,0 +1,591 @@
+use crate::{palette::NUM_COLORS, quantize::Quantizer};
+
+/// How texel coordinates outside of the texture are mapped back into it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl Wrap {
+    /// Maps texel coordinate `c` into `0..size`.
+    #[inline(always)]
+    pub fn apply(self, c: i32, size: u32) -> usize {
+        let size = size as i32;
+        (match self {
+            Wrap::Repeat => c.rem_euclid(size),
+            Wrap::Clamp => c.clamp(0, size - 1),
+            Wrap::Mirror => {
+                let c = c.rem_euclid(2 * size);
+                if c < size {
+                    c
+                } else {
+                    2 * size - 1 - c
+                }
+            }
+        }) as usize
+    }
+}
+
+/// Ordered dither matrices, indexed by `[y][x]`.
+pub const BAYER2X2: [[u8; 2]; 2] = [[0, 2], [3, 1]];
+pub const BAYER4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DitherKernel {
+    Bayer2x2,
+    Bayer4x4,
+}
+
+/// Jitters texture coordinates per pixel with an ordered dither pattern before nearest sampling,
+/// which averages out to bilinear filtering without leaving the palette (as in Unreal's software
+/// renderer).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dither {
+    pub kernel: DitherKernel,
+    /// Shifts the pattern, so passing a frame counter cycles every pixel through all offsets.
+    pub frame: u32,
+}
+
+impl Dither {
+    pub fn new(kernel: DitherKernel) -> Self {
+        Dither { kernel, frame: 0 }
+    }
+
+    /// Offsets in 0..1 for u and v at pixel `x`, `y`. The digits of the pattern value are swapped
+    /// for v, so every cell of a `size` x `size` grid over both offsets is hit once per tile.
+    #[inline(always)]
+    pub fn offset(&self, x: i32, y: i32) -> (f32, f32) {
+        let size = match self.kernel {
+            DitherKernel::Bayer2x2 => 2,
+            DitherKernel::Bayer4x4 => 4,
+        };
+        let lookup = |x: usize, y: usize| match self.kernel {
+            DitherKernel::Bayer2x2 => BAYER2X2[y][x],
+            DitherKernel::Bayer4x4 => BAYER4X4[y][x],
+        };
+        let frame = self.frame as i32;
+        let x = (x + frame % size).rem_euclid(size) as usize;
+        let y = (y + frame / size % size).rem_euclid(size) as usize;
+        let scale = 1.0 / (size * size) as f32;
+        let n = lookup(x, y) as i32;
+        let m = n % size * size + n / size;
+        ((n as f32 + 0.5) * scale, (m as f32 + 0.5) * scale)
+    }
+}
+
+/// How the truecolor shaders reconstruct a color between texels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    /// Weighted average of the 2x2 texels around the sample point, in the base level.
+    Bilinear,
+    /// Bilinear samples of the two mip levels around the level of detail, blended.
+    Trilinear,
+}
+
+/// Blends 8 bit channels of `a` and `b` by `t` in 0..1.
+#[inline(always)]
+pub fn lerp_rgb(a: u32, b: u32, t: f32) -> u32 {
+    let t = (t * 256.0) as u32;
+    let lerp = |shift: u32| {
+        let a = (a >> shift) & 0xff;
+        let b = (b >> shift) & 0xff;
+        ((a * (256 - t) + b * t) >> 8) << shift
+    };
+    lerp(0) | lerp(8) | lerp(16) | lerp(24)
+}
+
+/// Texel data of any size. Rows are `pitch` texels apart, which may be more than `width`.
+/// Masked textures have a transparent texel value, e.g. palette index 255 for grates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Texture<T = u8> {
+    width: u32,
+    height: u32,
+    pitch: usize,
+    texels: Vec<T>,
+    transparent: Option<T>,
+}
+
+impl<T: Copy> Texture<T> {
+    pub fn new(width: u32, height: u32, texels: Vec<T>) -> Self {
+        Self::with_pitch(width, height, width as usize, texels)
+    }
+
+    pub fn with_pitch(width: u32, height: u32, pitch: usize, texels: Vec<T>) -> Self {
+        assert!(width > 0 && height > 0 && pitch >= width as usize);
+        assert!(texels.len() >= pitch * (height as usize - 1) + width as usize);
+        Texture {
+            width,
+            height,
+            pitch,
+            texels,
+            transparent: None,
+        }
+    }
+
+    /// Marks texels equal to `transparent` as see-through.
+    pub fn with_transparent(self, transparent: T) -> Self {
+        Texture {
+            transparent: Some(transparent),
+            ..self
+        }
+    }
+
+    pub fn transparent(&self) -> Option<T> {
+        self.transparent
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pitch(&self) -> usize {
+        self.pitch
+    }
+
+    pub fn texels(&self) -> &[T] {
+        &self.texels
+    }
+
+    /// Texel at `x`, `y`, which must lie within the texture.
+    #[inline(always)]
+    pub fn texel(&self, x: usize, y: usize) -> T {
+        self.texels[x + y * self.pitch]
+    }
+
+    /// Nearest texel at texel coordinates `u`, `v`.
+    #[inline(always)]
+    pub fn sample(&self, u: f32, v: f32, wrap: Wrap) -> T {
+        let x = wrap.apply(u.floor() as i32, self.width);
+        let y = wrap.apply(v.floor() as i32, self.height);
+        self.texel(x, y)
+    }
+
+    /// Nearest texel after moving `u`, `v` by `offset` from `Dither::offset`. Uniform offsets
+    /// pick the texels around the sample point with the weights of bilinear filtering.
+    #[inline(always)]
+    pub fn sample_dithered(&self, u: f32, v: f32, offset: (f32, f32), wrap: Wrap) -> T {
+        self.sample(u - 0.5 + offset.0, v - 0.5 + offset.1, wrap)
+    }
+}
+
+impl<T: Copy + PartialEq> Texture<T> {
+    #[inline(always)]
+    pub fn is_transparent(&self, texel: T) -> bool {
+        self.transparent == Some(texel)
+    }
+}
+
+impl Texture<u8> {
+    /// Looks up every texel in `palette`.
+    pub fn to_rgb(&self, palette: &[u32; NUM_COLORS]) -> Texture<u32> {
+        let texels = (0..self.height as usize)
+            .flat_map(|y| (0..self.width as usize).map(move |x| (x, y)))
+            .map(|(x, y)| palette[self.texel(x, y) as usize])
+            .collect();
+        Texture::new(self.width, self.height, texels)
+    }
+}
+
+impl Texture<u32> {
+    /// Bilinear filtered color at texel coordinates `u`, `v`. Texel centers are at .5, so
+    /// sampling there returns the texel unchanged.
+    #[inline(always)]
+    pub fn sample_bilinear(&self, u: f32, v: f32, wrap: Wrap) -> u32 {
+        let (u, v) = (u - 0.5, v - 0.5);
+        let (u0, v0) = (u.floor(), v.floor());
+        let (x0, y0) = (u0 as i32, v0 as i32);
+        let x = [wrap.apply(x0, self.width), wrap.apply(x0 + 1, self.width)];
+        let y = [wrap.apply(y0, self.height), wrap.apply(y0 + 1, self.height)];
+        let (tu, tv) = (u - u0, v - v0);
+        let top = lerp_rgb(self.texel(x[0], y[0]), self.texel(x[1], y[0]), tu);
+        let bottom = lerp_rgb(self.texel(x[0], y[1]), self.texel(x[1], y[1]), tu);
+        lerp_rgb(top, bottom, tv)
+    }
+
+    /// Half size copy, averaging 2x2 blocks per 8 bit channel. Odd sizes reuse the last row or
+    /// column.
+    pub fn downsample(&self) -> Texture<u32> {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut texels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height as usize {
+            let ys = [2 * y, (2 * y + 1).min(self.height as usize - 1)];
+            for x in 0..width as usize {
+                let xs = [2 * x, (2 * x + 1).min(self.width as usize - 1)];
+                let mut sum = [0u32; 4];
+                for y in ys {
+                    for x in xs {
+                        let texel = self.texel(x, y);
+                        for (c, sum) in sum.iter_mut().enumerate() {
+                            *sum += (texel >> (8 * c)) & 0xff;
+                        }
+                    }
+                }
+                let texel = sum
+                    .iter()
+                    .enumerate()
+                    .fold(0, |texel, (c, sum)| texel | ((sum + 2) / 4) << (8 * c));
+                texels.push(texel);
+            }
+        }
+        Texture::new(width, height, texels)
+    }
+}
+
+/// Texture together with successively halved copies, down to 1x1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mipmaps<T = u8> {
+    levels: Vec<Texture<T>>,
+}
+
+impl<T: Copy> Mipmaps<T> {
+    /// Only the base level, i.e. no mipmapping.
+    pub fn single(texture: Texture<T>) -> Self {
+        Mipmaps {
+            levels: vec![texture],
+        }
+    }
+
+    pub fn levels(&self) -> &[Texture<T>] {
+        &self.levels
+    }
+
+    pub fn base(&self) -> &Texture<T> {
+        &self.levels[0]
+    }
+
+    /// Level `level`, or the smallest one if there are fewer.
+    #[inline(always)]
+    pub fn level(&self, level: usize) -> &Texture<T> {
+        &self.levels[level.min(self.levels.len() - 1)]
+    }
+
+    /// Nearest texel of `level`. `u` and `v` are texel coordinates of the base level.
+    #[inline(always)]
+    pub fn sample(&self, level: usize, u: f32, v: f32, wrap: Wrap) -> T {
+        let (texture, u, v) = self.scale(level, u, v);
+        texture.sample(u, v, wrap)
+    }
+
+    /// Like `sample`, with `offset` applied in texels of `level`.
+    #[inline(always)]
+    pub fn sample_dithered(
+        &self,
+        level: usize,
+        u: f32,
+        v: f32,
+        offset: (f32, f32),
+        wrap: Wrap,
+    ) -> T {
+        let (texture, u, v) = self.scale(level, u, v);
+        texture.sample_dithered(u, v, offset, wrap)
+    }
+
+    // level texture and base level texel coordinates converted to it
+    #[inline(always)]
+    fn scale(&self, level: usize, u: f32, v: f32) -> (&Texture<T>, f32, f32) {
+        let base = self.base();
+        let texture = self.level(level);
+        if texture.width == base.width && texture.height == base.height {
+            return (texture, u, v);
+        }
+        (
+            texture,
+            u * texture.width as f32 / base.width as f32,
+            v * texture.height as f32 / base.height as f32,
+        )
+    }
+}
+
+impl Mipmaps<u32> {
+    pub fn new(texture: Texture<u32>) -> Self {
+        let mut levels = vec![texture];
+        loop {
+            let last = &levels[levels.len() - 1];
+            if last.width == 1 && last.height == 1 {
+                break;
+            }
+            levels.push(last.downsample());
+        }
+        Mipmaps { levels }
+    }
+
+    /// Bilinear filtered color of `level`. `u` and `v` are texel coordinates of the base level.
+    #[inline(always)]
+    pub fn sample_bilinear(&self, level: usize, u: f32, v: f32, wrap: Wrap) -> u32 {
+        let (texture, u, v) = self.scale(level, u, v);
+        texture.sample_bilinear(u, v, wrap)
+    }
+
+    /// Bilinear samples of the levels below and above `lod`, blended by its fraction.
+    #[inline(always)]
+    pub fn sample_trilinear(&self, lod: f32, u: f32, v: f32, wrap: Wrap) -> u32 {
+        let lod = lod.max(0.0);
+        // clamped first, so a huge or infinite lod can't overflow below
+        let level = (lod as usize).min(self.levels.len() - 1);
+        if level + 1 == self.levels.len() {
+            return self.sample_bilinear(level, u, v, wrap);
+        }
+        lerp_rgb(
+            self.sample_bilinear(level, u, v, wrap),
+            self.sample_bilinear(level + 1, u, v, wrap),
+            lod.fract(),
+        )
+    }
+
+    /// Color at `u`, `v` reconstructed with `filter`. `lod` is only used for
+    /// trilinear filtering.
+    #[inline(always)]
+    pub fn sample_filtered(&self, filter: Filter, lod: f32, u: f32, v: f32, wrap: Wrap) -> u32 {
+        match filter {
+            Filter::Nearest => self.sample(0, u, v, wrap),
+            Filter::Bilinear => self.sample_bilinear(0, u, v, wrap),
+            Filter::Trilinear => self.sample_trilinear(lod, u, v, wrap),
+        }
+    }
+}
+
+impl Mipmaps<u8> {
+    /// Mip chain of a palettized texture. Levels are averaged in RGB and quantized back to
+    /// `palette`, the base level is kept as is.
+    ///
+    /// Masked textures are averaged with premultiplied coverage: a texel stays transparent if
+    /// most of the texels it covers are, others only take the color of the opaque ones and never
+    /// quantize to the transparent index.
+    pub fn from_indexed(texture: Texture, palette: &[u32; NUM_COLORS]) -> Self {
+        let transparent = match texture.transparent {
+            Some(transparent) => transparent,
+            None => {
+                let rgb = Mipmaps::new(texture.to_rgb(palette));
+                let quantizer = Quantizer::new(palette);
+                let mut levels = vec![texture];
+                levels.extend(rgb.levels[1..].iter().map(|level| {
+                    Texture::new(
+                        level.width,
+                        level.height,
+                        quantizer.quantize(level.texels()),
+                    )
+                }));
+                return Mipmaps { levels };
+            }
+        };
+
+        let mut rgba = texture.to_rgb(palette);
+        for (texel, index) in rgba.texels.iter_mut().zip(texture.texels.iter()) {
+            *texel = if *index == transparent {
+                0
+            } else {
+                *texel | 0xff000000
+            };
+        }
+        let rgba = Mipmaps::new(rgba);
+        let quantizer = Quantizer::excluding(palette, &[transparent]);
+        let mut levels = vec![texture];
+        levels.extend(rgba.levels[1..].iter().map(|level| {
+            let texels = level
+                .texels()
+                .iter()
+                .map(|texel| {
+                    let alpha = texel >> 24;
+                    if alpha < 0x80 {
+                        return None;
+                    }
+                    // undo the premultiplication
+                    Some((0..3).fold(0, |color, c| {
+                        let channel = (texel >> (8 * c)) & 0xff;
+                        color | ((channel * 255 + alpha / 2) / alpha).min(255) << (8 * c)
+                    }))
+                })
+                .collect::<Vec<_>>();
+            let opaque = texels.iter().flatten().copied().collect::<Vec<_>>();
+            let mut quantized = quantizer.quantize(&opaque).into_iter();
+            let texels = texels
+                .iter()
+                .map(|texel| match texel {
+                    Some(_) => quantized.next().unwrap(),
+                    None => transparent,
+                })
+                .collect();
+            Texture::new(level.width, level.height, texels).with_transparent(transparent)
+        }));
+        Mipmaps { levels }
+    }
+}
+
+/// Colors of the mip level debug view, starting with the base level.
+pub const MIP_DEBUG_COLORS: [u32; 8] = [
+    0xffffff, 0x0000ff, 0x00ff00, 0xff0000, 0x00ffff, 0xff00ff, 0xffff00, 0x808080,
+];
+
+#[test]
+fn test_wrap() {
+    let repeat = (-5..7)
+        .map(|c| Wrap::Repeat.apply(c, 3))
+        .collect::<Vec<_>>();
+    assert_eq!(repeat, [1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0]);
+    let clamp = (-2..5).map(|c| Wrap::Clamp.apply(c, 3)).collect::<Vec<_>>();
+    assert_eq!(clamp, [0, 0, 0, 1, 2, 2, 2]);
+    let mirror = (-4..8)
+        .map(|c| Wrap::Mirror.apply(c, 3))
+        .collect::<Vec<_>>();
+    assert_eq!(mirror, [2, 2, 1, 0, 0, 1, 2, 2, 1, 0, 0, 1]);
+}
+
+#[test]
+fn test_sample_non_power_of_two() {
+    // 3x2 texels in rows with a pitch of 4
+    let texture = Texture::with_pitch(3, 2, 4, vec![0, 1, 2, 99, 10, 11, 12]);
+    assert_eq!(texture.sample(0.5, 0.5, Wrap::Repeat), 0);
+    assert_eq!(texture.sample(2.9, 1.1, Wrap::Repeat), 12);
+    assert_eq!(texture.sample(3.2, 2.5, Wrap::Repeat), 0);
+    assert_eq!(texture.sample(-0.5, 0.0, Wrap::Repeat), 2);
+    assert_eq!(texture.sample(7.0, -3.0, Wrap::Clamp), 2);
+    assert_eq!(texture.sample(3.5, 2.5, Wrap::Mirror), 12);
+}
+
+#[test]
+fn test_mipmaps() {
+    let palette = crate::test_fixtures::gray_palette();
+    // 6x3 checkerboard of black and white
+    let texels = (0..18)
+        .map(|i| if (i % 6 + i / 6) % 2 == 0 { 0 } else { 254 })
+        .collect();
+    let mipmaps = Mipmaps::from_indexed(Texture::new(6, 3, texels), &palette);
+    let sizes = mipmaps
+        .levels()
+        .iter()
+        .map(|level| (level.width(), level.height()))
+        .collect::<Vec<_>>();
+    assert_eq!(sizes, [(6, 3), (3, 1), (1, 1)]);
+    // the checkerboard averages to gray
+    assert!(mipmaps.levels()[1].texels().iter().all(|t| *t == 127));
+    // base level texel coordinates address the same spot in every level
+    assert_eq!(mipmaps.sample(1, 5.5, 2.5, Wrap::Repeat), 127);
+    assert_eq!(
+        mipmaps.sample(5, 5.5, 2.5, Wrap::Repeat),
+        mipmaps.levels()[2].texel(0, 0)
+    );
+}
+
+#[test]
+fn test_dither() {
+    for (kernel, size) in [(DitherKernel::Bayer2x2, 2), (DitherKernel::Bayer4x4, 4)] {
+        let mut dither = Dither::new(kernel);
+        // every offset appears once per tile, for u and v alike
+        let mut us = Vec::new();
+        let mut vs = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                let (u, v) = dither.offset(x, y);
+                us.push(u);
+                vs.push(v);
+            }
+        }
+        us.sort_by(f32::total_cmp);
+        vs.sort_by(f32::total_cmp);
+        assert_eq!(us, vs);
+        let n = (size * size) as f32;
+        for (i, u) in us.iter().enumerate() {
+            assert_eq!(*u, (i as f32 + 0.5) / n);
+        }
+
+        // a pixel runs through all offsets when animated
+        let mut seen = (0..size * size)
+            .map(|frame| {
+                dither.frame = frame as u32;
+                dither.offset(1, 2).0
+            })
+            .collect::<Vec<_>>();
+        seen.sort_by(f32::total_cmp);
+        assert_eq!(seen, us);
+
+        // between four texels, each one is picked for a quarter of the tile
+        dither.frame = 0;
+        let texture = Texture::new(2, 2, vec![0u8, 1, 2, 3]);
+        let mut weights = [0; 4];
+        for y in 0..size {
+            for x in 0..size {
+                let texel = texture.sample_dithered(1.0, 1.0, dither.offset(x, y), Wrap::Clamp);
+                weights[texel as usize] += 1;
+            }
+        }
+        assert_eq!(weights.map(|weight| weight as f32 / n), [0.25; 4]);
+    }
+
+    // averaged over the pattern, dithered sampling weights the texels like bilinear filtering
+    let texture = Texture::new(2, 1, vec![0.0f32, 1.0]);
+    let dither = Dither::new(DitherKernel::Bayer4x4);
+    let mut sum = 0.0;
+    for y in 0..4 {
+        for x in 0..4 {
+            sum += texture.sample_dithered(0.75, 0.5, dither.offset(x, y), Wrap::Clamp);
+        }
+    }
+    assert_eq!(sum / 16.0, 0.25);
+}
+
+#[test]
+fn test_bilinear() {
+    let texture = Texture::new(2, 2, vec![0x000000, 0x0000ff, 0x00ff00, 0xff00ff]);
+    // texel centers are exact
+    assert_eq!(texture.sample_bilinear(0.5, 0.5, Wrap::Clamp), 0x000000);
+    assert_eq!(texture.sample_bilinear(1.5, 1.5, Wrap::Clamp), 0xff00ff);
+    // halfway between two texels
+    assert_eq!(texture.sample_bilinear(1.0, 0.5, Wrap::Clamp), 0x00007f);
+    assert_eq!(texture.sample_bilinear(1.0, 1.5, Wrap::Clamp), 0x7f7f7f);
+    // clamped outside of the texture, wrapped around with repeat
+    assert_eq!(texture.sample_bilinear(-3.0, 0.5, Wrap::Clamp), 0x000000);
+    assert_eq!(texture.sample_bilinear(0.0, 0.5, Wrap::Repeat), 0x00007f);
+
+    let mipmaps = Mipmaps::new(Texture::new(2, 2, vec![0xffffff; 4]));
+    let black = Mipmaps {
+        levels: vec![mipmaps.levels[0].clone(), Texture::new(1, 1, vec![0])],
+    };
+    assert_eq!(
+        black.sample_trilinear(0.0, 1.0, 1.0, Wrap::Repeat),
+        0xffffff
+    );
+    assert_eq!(
+        black.sample_trilinear(0.5, 1.0, 1.0, Wrap::Repeat),
+        0x7f7f7f
+    );
+    assert_eq!(
+        black.sample_trilinear(7.0, 1.0, 1.0, Wrap::Repeat),
+        0x000000
+    );
+    // beyond the last level, even for an infinite lod
+    assert_eq!(
+        black.sample_trilinear(f32::INFINITY, 1.0, 1.0, Wrap::Repeat),
+        0x000000
+    );
+    assert_eq!(
+        black.sample_filtered(Filter::Bilinear, 1.0, 1.0, 1.0, Wrap::Repeat),
+        0xffffff
+    );
+}
+
+#[test]
+fn test_masked_mipmaps() {
+    let palette = crate::test_fixtures::gray_palette();
+    // 4x2 texels: a white column next to transparent ones, and one fully transparent block.
+    // Index 255 is also the closest color to white, but must not be picked for opaque texels.
+    let texels = vec![254, 255, 255, 255, 254, 255, 255, 255];
+    let texture = Texture::new(4, 2, texels).with_transparent(255);
+    assert!(texture.is_transparent(255));
+    assert!(!texture.is_transparent(254));
+    let mipmaps = Mipmaps::from_indexed(texture, &palette);
+    // half coverage stays opaque with the color of the opaque texels only, up to rounding
+    let level = mipmaps.levels()[1].texels();
+    assert!((253..=254).contains(&level[0]));
+    assert_eq!(level[1], 255);
+    assert_eq!(mipmaps.levels()[1].transparent(), Some(255));
+    // a quarter coverage turns transparent
+    assert_eq!(mipmaps.levels()[2].texels(), [255]);
+}
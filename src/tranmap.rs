@@ -0,0 +1,113 @@
+use std::{fs, io, path::Path};
+
+use crate::{palette::NUM_COLORS, quantize::Quantizer};
+
+/// Doom style translucency table: for every pair of palette indices the index closest to the
+/// first color blended over the second one with `opacity`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tranmap {
+    opacity: f32,
+    table: Vec<[u8; NUM_COLORS]>,
+}
+
+impl Tranmap {
+    pub fn new(palette: &[u32; NUM_COLORS], opacity: f32) -> Self {
+        let blend = |src: u32, dst: u32, shift: u32| {
+            let src = ((src >> shift) & 0xff) as f32;
+            let dst = ((dst >> shift) & 0xff) as f32;
+            ((src * opacity + dst * (1.0 - opacity)).round() as u32) << shift
+        };
+        let rgb = palette
+            .iter()
+            .flat_map(|src| {
+                palette.iter().map(move |dst| {
+                    blend(*src, *dst, 0) | blend(*src, *dst, 8) | blend(*src, *dst, 16)
+                })
+            })
+            .collect::<Vec<_>>();
+        let table = Quantizer::new(palette)
+            .quantize(&rgb)
+            .chunks(NUM_COLORS)
+            .map(|row| row.try_into().unwrap())
+            .collect();
+        Tranmap { opacity, table }
+    }
+
+    /// Reads the table from the cache file at `path` if it was generated for the same palette
+    /// and opacity, otherwise generates it and writes the cache. The table comes with the result
+    /// of writing the cache, it is usable either way.
+    pub fn cached<P>(palette: &[u32; NUM_COLORS], opacity: f32, path: P) -> (Self, io::Result<()>)
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let header = Self::header(palette, opacity);
+        if let Ok(data) = fs::read(path) {
+            if data.len() == header.len() + NUM_COLORS * NUM_COLORS && data.starts_with(&header) {
+                let table = data[header.len()..]
+                    .chunks(NUM_COLORS)
+                    .map(|row| row.try_into().unwrap())
+                    .collect();
+                return (Tranmap { opacity, table }, Ok(()));
+            }
+        }
+        let tranmap = Self::new(palette, opacity);
+        let written = tranmap.write(path, &header);
+        (tranmap, written)
+    }
+
+    pub fn opacity(&self) -> f32 {
+        self.opacity
+    }
+
+    /// Palette index of `src` drawn with the table's opacity over `dst`.
+    #[inline(always)]
+    pub fn blend(&self, src: u8, dst: u8) -> u8 {
+        self.table[src as usize][dst as usize]
+    }
+
+    // palette as rgb triplets and the opacity, identifying the table in a cache file
+    fn header(palette: &[u32; NUM_COLORS], opacity: f32) -> Vec<u8> {
+        let mut header = palette
+            .iter()
+            .flat_map(|color| [*color as u8, (color >> 8) as u8, (color >> 16) as u8])
+            .collect::<Vec<_>>();
+        header.extend(opacity.to_le_bytes());
+        header
+    }
+
+    fn write(&self, path: &Path, header: &[u8]) -> io::Result<()> {
+        let mut data = header.to_vec();
+        for row in self.table.iter() {
+            data.extend_from_slice(row);
+        }
+        fs::write(path, data)
+    }
+}
+
+#[test]
+fn test_tranmap() {
+    let palette = crate::test_fixtures::gray_palette();
+    let half = Tranmap::new(&palette, 0.5);
+    assert_eq!(half.blend(200, 100), 150);
+    assert_eq!(half.blend(100, 200), 150);
+    let quarter = Tranmap::new(&palette, 0.25);
+    assert_eq!(quarter.blend(200, 100), 125);
+    let opaque = Tranmap::new(&palette, 1.0);
+    assert!((0..=255).all(|i| opaque.blend(i, 255 - i) == i));
+
+    let path = std::env::temp_dir().join(format!("test_tranmap_{}.bin", std::process::id()));
+    let (tranmap, written) = Tranmap::cached(&palette, 0.25, &path);
+    assert_eq!(tranmap, quarter);
+    written.unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().len(), 3 * 256 + 4 + 256 * 256);
+    // read back from the cache, regenerated for a different opacity
+    assert_eq!(Tranmap::cached(&palette, 0.25, &path).0, quarter);
+    assert_eq!(Tranmap::cached(&palette, 0.5, &path).0, half);
+    fs::remove_file(&path).unwrap();
+
+    // a cache that can't be written still gives the table, along with the error
+    let (tranmap, written) = Tranmap::cached(&palette, 0.5, path.join("missing_dir"));
+    assert_eq!(tranmap, half);
+    assert!(written.is_err());
+}